@@ -8,6 +8,7 @@ use std::io::{Error, ErrorKind, Write};
 use bevy::prelude::*;
 
 /// The amount of Z snaps per X/Y snap. e.g. Number of vertical steps in a cell block.
+#[allow(dead_code)]
 const Z_SNAP: u32 = 10;
 
 /// A wrapper around a fixed point value using the `fixed` crate.
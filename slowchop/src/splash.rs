@@ -1,9 +1,5 @@
-use bevy::ecs::component::Component;
-use bevy::ecs::system::QuerySingleError;
 use bevy::prelude::*;
 use std::fmt::Debug;
-use std::hash::Hash;
-use std::marker::PhantomData;
 
 pub struct SplashScreen;
 
@@ -44,7 +40,7 @@ fn run(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut config: ResMut<SplashScreenState>,
-    mut keys: ResMut<Input<KeyCode>>,
+    keys: ResMut<Input<KeyCode>>,
     mut mouse_button: ResMut<Input<MouseButton>>,
 ) {
     match &mut *config {
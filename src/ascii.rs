@@ -0,0 +1,363 @@
+//! A text format for maps with one character per cell, and one grid per layer, e.g.
+//!
+//! ```text
+//! origin 0 2
+//! [floor]
+//!  ...
+//! [structure]
+//! r-7
+//! |E|
+//! L-J
+//! ```
+//!
+//! `origin` is the cell of the first character of the first line. Lines go down the map, so each
+//! line is one cell lower than the one above it. Spaces are empty cells, and lines can stop early.
+//!
+//! Anything the grids can't show, like background images, items that aren't on the grid, other
+//! rotations, or non-default properties, goes in an `[extra]` section with one JSON item per line.
+//! Triggers go in a `[triggers]` section the same way.
+
+use crate::map::{Item, ItemInfo, Layer, Map};
+use crate::position::{FlexPosition, GridPosition};
+use crate::triggers::Trigger;
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::Path;
+
+/// Each character, and the item and rotation it stands for.
+const LEGEND: [(char, Item, f32); 15] = [
+    ('.', Item::GeneralTile, 0.0),
+    (':', Item::CellTile, 0.0),
+    ('=', Item::Wire, 0.0),
+    ('I', Item::Wire, 90.0),
+    ('-', Item::Wall, 0.0),
+    ('|', Item::Wall, 90.0),
+    // Corners look like the box drawing characters ┘ ┐ ┌ └.
+    ('J', Item::WallCorner, 0.0),
+    ('7', Item::WallCorner, 90.0),
+    ('r', Item::WallCorner, 180.0),
+    ('L', Item::WallCorner, 270.0),
+    ('D', Item::Door, 0.0),
+    ('d', Item::Door, 90.0),
+    ('E', Item::Exit, 0.0),
+    ('W', Item::Warden, 0.0),
+    ('P', Item::Prisoner, 0.0),
+];
+
+const EMPTY: char = ' ';
+
+fn section_name(layer: Layer) -> String {
+    layer.name().to_lowercase()
+}
+
+fn char_for(item_info: &ItemInfo) -> Option<char> {
+    if !item_info.properties.is_default() {
+        return None;
+    }
+    LEGEND
+        .iter()
+        .find(|(_, item, rotation)| *item == item_info.item && *rotation == item_info.rotation)
+        .map(|(c, _, _)| *c)
+}
+
+fn item_for(c: char) -> Option<(Item, f32)> {
+    LEGEND
+        .iter()
+        .find(|(legend, _, _)| *legend == c)
+        .map(|(_, item, rotation)| (item.clone(), *rotation))
+}
+
+/// Items come out in layer order, then row by row, with extras at the end. Two grid items on the
+/// same layer in the same cell can't both be shown, so the second one goes in the extras.
+pub fn to_ascii(map: &Map) -> String {
+    let mut grids: HashMap<Layer, HashMap<GridPosition, char>> = HashMap::new();
+    let mut extras = vec![];
+    for item_info in &map.items {
+        let cell = match (&item_info.position, char_for(item_info)) {
+            (FlexPosition::Grid(cell), Some(_)) => *cell,
+            _ => {
+                extras.push(item_info);
+                continue;
+            }
+        };
+        let grid = grids.entry(item_info.item.layer()).or_default();
+        match grid.entry(cell) {
+            Entry::Occupied(_) => extras.push(item_info),
+            Entry::Vacant(e) => {
+                e.insert(char_for(item_info).unwrap());
+            }
+        }
+    }
+
+    let cells = grids.values().flat_map(|g| g.keys());
+    let min_x = cells.clone().map(|c| c.0.x).min().unwrap_or(0);
+    let max_x = cells.clone().map(|c| c.0.x).max().unwrap_or(0);
+    let min_y = cells.clone().map(|c| c.0.y).min().unwrap_or(0);
+    let max_y = cells.map(|c| c.0.y).max().unwrap_or(0);
+
+    let mut out = format!("origin {} {}\n", min_x, max_y);
+    for layer in Layer::all().iter() {
+        let grid = match grids.get(layer) {
+            Some(g) => g,
+            None => continue,
+        };
+        out += &format!("[{}]\n", section_name(*layer));
+        for y in (min_y..=max_y).rev() {
+            let line: String = (min_x..=max_x)
+                .map(|x| *grid.get(&GridPosition::new(x, y)).unwrap_or(&EMPTY))
+                .collect();
+            out += line.trim_end();
+            out += "\n";
+        }
+    }
+
+    if !extras.is_empty() {
+        out += "[extra]\n";
+        for item_info in extras {
+            out += &serde_json::to_string(item_info).unwrap();
+            out += "\n";
+        }
+    }
+    if !map.triggers.is_empty() {
+        out += "[triggers]\n";
+        for trigger in &map.triggers {
+            out += &serde_json::to_string(trigger).unwrap();
+            out += "\n";
+        }
+    }
+    out
+}
+
+enum Section {
+    Layer(Layer, i32),
+    Extra,
+    Triggers,
+}
+
+pub fn from_ascii(text: &str) -> Result<Map, String> {
+    let mut lines = text.lines().enumerate();
+    let origin = match lines.next() {
+        Some((_, line)) => parse_origin(line)?,
+        None => return Err("Expected `origin <x> <y>` on the first line.".into()),
+    };
+
+    let mut map = Map::new();
+    let mut section = None;
+    for (idx, line) in lines {
+        let line_number = idx + 1;
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = &line[1..line.len() - 1];
+            section = if name == "extra" {
+                Some(Section::Extra)
+            } else if name == "triggers" {
+                Some(Section::Triggers)
+            } else {
+                let layer = Layer::all()
+                    .iter()
+                    .find(|l| section_name(**l) == name)
+                    .cloned()
+                    .ok_or_else(|| format!("Line {}: unknown section [{}].", line_number, name))?;
+                Some(Section::Layer(layer, 0))
+            };
+            continue;
+        }
+
+        match &mut section {
+            None => {
+                if !line.trim().is_empty() {
+                    return Err(format!("Line {}: expected a [section].", line_number));
+                }
+            }
+            Some(Section::Extra) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let item_info: ItemInfo = serde_json::from_str(line)
+                    .map_err(|e| format!("Line {}: {}", line_number, e))?;
+                map.items.push(item_info);
+            }
+            Some(Section::Triggers) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let trigger: Trigger = serde_json::from_str(line)
+                    .map_err(|e| format!("Line {}: {}", line_number, e))?;
+                map.triggers.push(trigger);
+            }
+            Some(Section::Layer(layer, row)) => {
+                let y = origin.0.y - *row;
+                *row += 1;
+                for (column, c) in line.chars().enumerate() {
+                    if c == EMPTY {
+                        continue;
+                    }
+                    let (item, rotation) = item_for(c).ok_or_else(|| {
+                        format!("Line {}: unknown character {:?}.", line_number, c)
+                    })?;
+                    if item.layer() != *layer {
+                        return Err(format!(
+                            "Line {}: {:?} belongs in [{}].",
+                            line_number,
+                            item,
+                            section_name(item.layer())
+                        ));
+                    }
+                    let cell = GridPosition::new(origin.0.x + column as i32, y);
+                    map.items
+                        .push(ItemInfo::new(item, FlexPosition::Grid(cell), rotation));
+                }
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Text maps end in `.txt`.
+pub fn is_ascii_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "txt")
+}
+
+fn parse_origin(line: &str) -> Result<GridPosition, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["origin", x, y] => {
+            let x = x.parse().map_err(|_| format!("Bad origin x {:?}.", x))?;
+            let y = y.parse().map_err(|_| format!("Bad origin y {:?}.", y))?;
+            Ok(GridPosition::new(x, y))
+        }
+        _ => Err(format!(
+            "Expected `origin <x> <y>` on the first line, not {:?}.",
+            line
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::ItemProperties;
+    use crate::position::Position;
+    use crate::triggers::{Action, Condition, Selector};
+    use nalgebra::Vector2;
+
+    fn item(item: Item, x: i32, y: i32, rotation: f32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), rotation)
+    }
+
+    /// Item order isn't kept, so compare them sorted.
+    fn sorted(map: &Map) -> Vec<String> {
+        let mut items: Vec<String> = map
+            .items
+            .iter()
+            .map(|i| serde_json::to_string(i).unwrap())
+            .collect();
+        items.sort();
+        items
+    }
+
+    fn assert_round_trip(map: &Map) {
+        let text = to_ascii(map);
+        let back = from_ascii(&text).unwrap();
+        assert_eq!(sorted(map), sorted(&back), "{}", text);
+    }
+
+    #[test]
+    fn reads_a_small_room() {
+        let text = "\
+origin 0 2
+[floor]
+
+ .
+[structure]
+r-7
+|E|
+L-J
+";
+        let map = from_ascii(text).unwrap();
+        assert_eq!(map.items.len(), 10);
+        assert!(map.items.contains(&item(Item::GeneralTile, 1, 1, 0.0)));
+        assert!(map.items.contains(&item(Item::WallCorner, 0, 2, 180.0)));
+        assert!(map.items.contains(&item(Item::WallCorner, 2, 0, 0.0)));
+        assert!(map.items.contains(&item(Item::Wall, 0, 1, 90.0)));
+        assert!(map.items.contains(&item(Item::Exit, 1, 1, 0.0)));
+    }
+
+    #[test]
+    fn writes_rows_from_the_top() {
+        let map = Map::from_items(vec![
+            item(Item::Wall, -1, -1, 0.0),
+            item(Item::Prisoner, 0, 0, 0.0),
+        ]);
+        assert_eq!(
+            to_ascii(&map),
+            "origin -1 0\n[structure]\n\n-\n[actors]\n P\n\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_grid_items() {
+        let map = Map::from_items(vec![
+            item(Item::GeneralTile, 0, 0, 0.0),
+            item(Item::Wire, 0, 0, 90.0),
+            item(Item::Door, 3, 0, 0.0),
+            item(Item::Warden, 0, 0, 0.0),
+            item(Item::WallCorner, -2, 5, 270.0),
+        ]);
+        assert_round_trip(&map);
+    }
+
+    #[test]
+    fn round_trips_extras() {
+        let mut door = item(Item::Door, 0, 0, 0.0);
+        door.properties = ItemProperties {
+            door_open: true,
+            circuit: 2,
+            ..Default::default()
+        };
+        let map = Map::from_items(vec![
+            door,
+            item(Item::Background("menus/logo.png".into()), 0, 0, 0.0),
+            item(Item::Wall, 1, 1, 180.0),
+            // Two on the same cell and layer.
+            item(Item::Prisoner, 4, 4, 0.0),
+            item(Item::Prisoner, 4, 4, 0.0),
+            ItemInfo::new(
+                Item::Warden,
+                FlexPosition::Position(Position(Vector2::new(0.5, 0.25))),
+                0.0,
+            ),
+        ]);
+        assert_round_trip(&map);
+    }
+
+    #[test]
+    fn round_trips_triggers() {
+        let mut map = Map::from_items(vec![item(Item::Wire, 0, 0, 0.0)]);
+        map.triggers.push(Trigger {
+            name: "lockdown".into(),
+            when: Condition::After(120.0),
+            then: vec![
+                Action::DamageWires(Selector::Circuit(2)),
+                Action::Notify("Block B is failing.".into()),
+            ],
+            repeat: false,
+        });
+        let back = from_ascii(&to_ascii(&map)).unwrap();
+        assert_eq!(back.triggers, map.triggers);
+    }
+
+    #[test]
+    fn round_trips_level1() {
+        let map = Map::load(std::path::Path::new("assets/maps/level1.json")).unwrap();
+        assert_round_trip(&map);
+    }
+
+    #[test]
+    fn rejects_items_in_the_wrong_layer() {
+        assert!(from_ascii("origin 0 0\n[floor]\nP\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_characters() {
+        assert!(from_ascii("origin 0 0\n[structure]\n?\n").is_err());
+    }
+}
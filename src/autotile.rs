@@ -0,0 +1,194 @@
+use crate::map::{Item, ItemInfo, Map};
+use crate::position::GridPosition;
+use bevy::utils::HashSet;
+
+/// Doors sit inside walls, so walls next to them should join up to them.
+fn is_wall_like(item: &Item) -> bool {
+    matches!(item, Item::Wall | Item::WallCorner | Item::Door)
+}
+
+/// Only these are changed by auto-tiling. Doors keep their rotation.
+fn is_tileable(item: &Item) -> bool {
+    matches!(item, Item::Wall | Item::WallCorner)
+}
+
+pub fn wall_cells(items: &[ItemInfo]) -> HashSet<GridPosition> {
+    items
+        .iter()
+        .filter(|i| is_wall_like(&i.item))
+        .flat_map(|i| i.cells())
+        .collect()
+}
+
+/// Picks the wall piece and rotation for a cell based on which neighbouring cells have walls.
+///
+/// Horizontal walls are 0° and vertical walls are 90°. Corners are 0° when joining north and west,
+/// then rotate anticlockwise: 90° south and west, 180° south and east, 270° north and east.
+///
+/// There are no T or cross pieces, so those become a straight wall along the axis that has walls on
+/// both sides. Returns `None` for a wall on its own, which keeps whatever it was.
+pub fn tile_for(cell: &GridPosition, walls: &HashSet<GridPosition>) -> Option<(Item, f32)> {
+    let has = |x, y| walls.contains(&(cell + &GridPosition::new(x, y)));
+    let (n, s, e, w) = (has(0, 1), has(0, -1), has(1, 0), has(-1, 0));
+
+    let tile = match (n, s, e, w) {
+        (false, false, false, false) => return None,
+        (true, false, false, true) => (Item::WallCorner, 0.0),
+        (false, true, false, true) => (Item::WallCorner, 90.0),
+        (false, true, true, false) => (Item::WallCorner, 180.0),
+        (true, false, true, false) => (Item::WallCorner, 270.0),
+        (true, true, _, _) => (Item::Wall, 90.0),
+        (_, _, true, true) => (Item::Wall, 0.0),
+        (true, false, false, false) | (false, true, false, false) => (Item::Wall, 90.0),
+        _ => (Item::Wall, 0.0),
+    };
+    Some(tile)
+}
+
+/// Returns the index and new value of each wall that needs to change.
+///
+/// When `only` is given, walls outside those cells are left alone, but still count as neighbours.
+pub fn autotile_items(
+    items: &[ItemInfo],
+    only: Option<&HashSet<GridPosition>>,
+) -> Vec<(usize, ItemInfo)> {
+    let walls = wall_cells(items);
+    let mut changes = vec![];
+    for (idx, item_info) in items.iter().enumerate() {
+        if !is_tileable(&item_info.item) {
+            continue;
+        }
+        let cell = item_info.position.nearest_cell_grid_pos();
+        if let Some(only) = only {
+            if !only.contains(&cell) {
+                continue;
+            }
+        }
+        let (item, rotation) = match tile_for(&cell, &walls) {
+            Some(t) => t,
+            None => continue,
+        };
+        if item == item_info.item && rotation == item_info.rotation {
+            continue;
+        }
+        let mut new = item_info.clone();
+        new.item = item;
+        new.rotation = rotation;
+        changes.push((idx, new));
+    }
+    changes
+}
+
+pub fn autotile(map: &mut Map) {
+    for (idx, item_info) in autotile_items(&map.items, None) {
+        map.items[idx] = item_info;
+    }
+}
+
+/// The cells and their eight neighbours.
+pub fn with_neighbours(cells: impl Iterator<Item = GridPosition>) -> HashSet<GridPosition> {
+    let mut found = HashSet::default();
+    for cell in cells {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                found.insert(&cell + &GridPosition::new(x, y));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::FlexPosition;
+
+    fn cells(cells: &[(i32, i32)]) -> HashSet<GridPosition> {
+        cells
+            .iter()
+            .map(|(x, y)| GridPosition::new(*x, *y))
+            .collect()
+    }
+
+    fn wall(x: i32, y: i32) -> ItemInfo {
+        ItemInfo::new(Item::Wall, FlexPosition::Grid(GridPosition::new(x, y)), 0.0)
+    }
+
+    #[test]
+    fn tiles_for_each_pattern() {
+        let n = (0, 1);
+        let s = (0, -1);
+        let e = (1, 0);
+        let w = (-1, 0);
+        let table: &[(&str, &[(i32, i32)], Option<(Item, f32)>)] = &[
+            ("isolated", &[], None),
+            ("horizontal", &[e, w], Some((Item::Wall, 0.0))),
+            ("vertical", &[n, s], Some((Item::Wall, 90.0))),
+            ("end to the east", &[e], Some((Item::Wall, 0.0))),
+            ("end to the north", &[n], Some((Item::Wall, 90.0))),
+            ("north west corner", &[n, w], Some((Item::WallCorner, 0.0))),
+            ("south west corner", &[s, w], Some((Item::WallCorner, 90.0))),
+            (
+                "south east corner",
+                &[s, e],
+                Some((Item::WallCorner, 180.0)),
+            ),
+            (
+                "north east corner",
+                &[n, e],
+                Some((Item::WallCorner, 270.0)),
+            ),
+            ("T without south", &[n, e, w], Some((Item::Wall, 0.0))),
+            ("T without west", &[n, s, e], Some((Item::Wall, 90.0))),
+            ("cross", &[n, s, e, w], Some((Item::Wall, 90.0))),
+            // Diagonals aren't neighbours.
+            ("diagonals only", &[(1, 1), (-1, -1)], None),
+        ];
+        for (name, neighbours, expected) in table {
+            let mut walls = cells(neighbours);
+            walls.insert(GridPosition::zero());
+            assert_eq!(
+                tile_for(&GridPosition::zero(), &walls),
+                *expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn only_changes_walls_in_the_given_cells() {
+        // An L shape, with every wall still horizontal.
+        let items = vec![wall(0, 0), wall(1, 0), wall(0, 1), wall(0, 2)];
+        let changed = |only: Option<&HashSet<GridPosition>>| -> Vec<(usize, Item, f32)> {
+            autotile_items(&items, only)
+                .into_iter()
+                .map(|(idx, i)| (idx, i.item, i.rotation))
+                .collect()
+        };
+
+        assert_eq!(
+            changed(None),
+            vec![
+                (0, Item::WallCorner, 270.0),
+                (2, Item::Wall, 90.0),
+                (3, Item::Wall, 90.0),
+            ]
+        );
+        assert_eq!(
+            changed(Some(&cells(&[(0, 0), (1, 0)]))),
+            vec![(0, Item::WallCorner, 270.0)]
+        );
+        assert!(changed(Some(&cells(&[(1, 0), (5, 5)]))).is_empty());
+    }
+
+    #[test]
+    fn with_neighbours_adds_the_surrounding_cells() {
+        let found =
+            with_neighbours(vec![GridPosition::zero(), GridPosition::new(1, 0)].into_iter());
+        assert_eq!(found.len(), 12);
+        assert!(found.contains(&GridPosition::new(-1, -1)));
+        assert!(found.contains(&GridPosition::new(2, 1)));
+        assert!(!found.contains(&GridPosition::new(3, 0)));
+    }
+}
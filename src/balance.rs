@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Used when a level doesn't have its own balance file.
+pub const DEFAULT_BALANCE_PATH: &str = "assets/balance.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn all() -> [Difficulty; 3] {
+        [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+}
+
+/// Tunable numbers for the game. Speeds and ranges are in grid units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Balance {
+    pub warden_speed: f64,
+    pub prisoner_speed_min: f64,
+    pub prisoner_speed_max: f64,
+    /// Each fixed step, a wire has a one in this many chance of being damaged.
+    pub wire_damage_one_in: u32,
+    /// How long a wire smokes before it breaks.
+    pub wire_damaged_seconds: f32,
+    pub smoke_interval_seconds: f32,
+    /// How close the warden needs to be to catch a prisoner or fix a wire.
+    pub interaction_range: f64,
+    /// Seconds per fixed update. Read by the fixed stage every frame, so levels can change it too.
+    pub fixed_step: f64,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self {
+            warden_speed: 0.1,
+            prisoner_speed_min: 0.04,
+            prisoner_speed_max: 0.06,
+            // 1000 seems good
+            // 100 is good for testing
+            wire_damage_one_in: 1000,
+            wire_damaged_seconds: 2.0,
+            smoke_interval_seconds: 0.5,
+            interaction_range: 1.5,
+            fixed_step: 1.0 / 60.0,
+        }
+    }
+}
+
+/// Only the fields that a difficulty changes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BalanceOverride {
+    pub warden_speed: Option<f64>,
+    pub prisoner_speed_min: Option<f64>,
+    pub prisoner_speed_max: Option<f64>,
+    pub wire_damage_one_in: Option<u32>,
+    pub wire_damaged_seconds: Option<f32>,
+    pub smoke_interval_seconds: Option<f32>,
+    pub interaction_range: Option<f64>,
+}
+
+impl BalanceOverride {
+    fn apply(&self, balance: &mut Balance) {
+        fn set<T: Clone>(field: &mut T, value: &Option<T>) {
+            if let Some(v) = value {
+                *field = v.clone();
+            }
+        }
+        set(&mut balance.warden_speed, &self.warden_speed);
+        set(&mut balance.prisoner_speed_min, &self.prisoner_speed_min);
+        set(&mut balance.prisoner_speed_max, &self.prisoner_speed_max);
+        set(&mut balance.wire_damage_one_in, &self.wire_damage_one_in);
+        set(&mut balance.wire_damaged_seconds, &self.wire_damaged_seconds);
+        set(&mut balance.smoke_interval_seconds, &self.smoke_interval_seconds);
+        set(&mut balance.interaction_range, &self.interaction_range);
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BalanceFile {
+    #[serde(flatten)]
+    pub balance: Balance,
+    pub difficulties: HashMap<Difficulty, BalanceOverride>,
+}
+
+impl Balance {
+    /// The balance file for a level sits beside its map, e.g. `level1.json` uses
+    /// `level1.balance.json`. Each layer only needs the values it changes: the built in values,
+    /// then `DEFAULT_BALANCE_PATH`, then the level's file if it has a map file, then the chosen
+    /// difficulty from either file.
+    pub fn load_for_level(map_path: Option<&Path>, difficulty: Difficulty) -> Self {
+        let default = Self::read(Path::new(DEFAULT_BALANCE_PATH));
+        let level = map_path.and_then(|p| Self::read(&Self::path_for_map(p)));
+        Self::layered(default, level, difficulty)
+    }
+
+    fn layered(default: Option<Value>, level: Option<Value>, difficulty: Difficulty) -> Self {
+        let mut merged = default.unwrap_or(Value::Null);
+        if let Some(level) = level {
+            merge(&mut merged, level);
+        }
+
+        let file = Self::parse(merged);
+        let mut balance = file.balance;
+        if let Some(o) = file.difficulties.get(&difficulty) {
+            o.apply(&mut balance);
+        }
+        balance
+    }
+
+    /// The default balance file, without any difficulty applied.
+    pub fn load_default() -> Self {
+        let value = Self::read(Path::new(DEFAULT_BALANCE_PATH)).unwrap_or(Value::Null);
+        Self::parse(value).balance
+    }
+
+    pub fn path_for_map(map_path: &Path) -> PathBuf {
+        let stem = map_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        map_path.with_file_name(format!("{}.balance.json", stem))
+    }
+
+    /// Reads a balance file as JSON so it can be layered over another one. Files that don't
+    /// parse as a `BalanceFile` are skipped.
+    fn read(path: &Path) -> Option<Value> {
+        let f = File::open(path).ok()?;
+        let value: Value = match serde_json::from_reader(f) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Could not parse balance file {:?}: {}", path, e);
+                return None;
+            }
+        };
+        match BalanceFile::deserialize(&value) {
+            Ok(_) => {
+                info!("Loaded balance from {:?}", path);
+                Some(value)
+            }
+            Err(e) => {
+                warn!("Could not parse balance file {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn parse(value: Value) -> BalanceFile {
+        if value.is_null() {
+            return BalanceFile::default();
+        }
+        serde_json::from_value(value).unwrap_or_else(|e| {
+            warn!("Could not combine balance files: {}", e);
+            BalanceFile::default()
+        })
+    }
+}
+
+/// Copies `layer` over `base`, keeping anything in `base` that `layer` doesn't mention.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn level_files_only_change_what_they_mention() {
+        let balance = Balance::layered(
+            Some(json!({ "warden_speed": 0.2, "interaction_range": 2.0 })),
+            Some(json!({ "warden_speed": 0.3 })),
+            Difficulty::Normal,
+        );
+        assert_eq!(balance.warden_speed, 0.3);
+        assert_eq!(balance.interaction_range, 2.0);
+        assert_eq!(
+            balance.wire_damage_one_in,
+            Balance::default().wire_damage_one_in
+        );
+    }
+
+    #[test]
+    fn difficulties_come_from_either_file() {
+        let default = json!({
+            "difficulties": {
+                "Hard": { "warden_speed": 0.05, "interaction_range": 1.0 },
+                "Easy": { "warden_speed": 0.2 }
+            }
+        });
+        let level = json!({ "difficulties": { "Hard": { "warden_speed": 0.07 } } });
+
+        let hard = Balance::layered(Some(default.clone()), Some(level.clone()), Difficulty::Hard);
+        assert_eq!(hard.warden_speed, 0.07);
+        assert_eq!(hard.interaction_range, 1.0);
+
+        let easy = Balance::layered(Some(default), Some(level), Difficulty::Easy);
+        assert_eq!(easy.warden_speed, 0.2);
+    }
+
+    #[test]
+    fn missing_files_use_the_built_in_values() {
+        assert_eq!(
+            Balance::layered(None, None, Difficulty::Hard),
+            Balance::default()
+        );
+    }
+}
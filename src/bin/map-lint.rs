@@ -0,0 +1,42 @@
+//! Checks map files for problems without starting the game.
+//!
+//! ```bash
+//! cargo run --bin map-lint -- assets/maps/*.json
+//! ```
+//!
+//! Exits with 1 if any map has a problem, or 2 if no maps were given.
+
+use game::lint::lint;
+use game::map::Map;
+use std::env;
+use std::path::Path;
+use std::process;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() || paths.iter().any(|p| p == "-h" || p == "--help") {
+        eprintln!("Usage: map-lint <MAP.json>...");
+        process::exit(2);
+    }
+
+    let mut problems = 0;
+    for path in &paths {
+        let map = match Map::load(Path::new(path)) {
+            Ok(m) => m,
+            Err(e) => {
+                println!("{}: {}", path, e);
+                problems += 1;
+                continue;
+            }
+        };
+        for diagnostic in lint(&map) {
+            println!("{}: {}", path, diagnostic);
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        eprintln!("{} problem(s) in {} map(s).", problems, paths.len());
+        process::exit(1);
+    }
+}
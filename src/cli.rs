@@ -0,0 +1,377 @@
+use crate::balance::Difficulty;
+use crate::generator::GeneratorParams;
+use crate::AppState;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+Please Do Not Escape
+
+USAGE:
+    please-dont-escape [OPTIONS] [solo|editor]
+    please-dont-escape render <MAP> <OUT.png> [--scale <FACTOR>] [--assets <DIR>]
+    please-dont-escape convert <MAP> <OUT>
+
+OPTIONS:
+    --state <STATE>        Where to start: splash, menu, game or editor [default: menu]
+    --map <FILE>           Map to play, or to open in the editor
+    --seed <SEED>          Play a generated prison from this seed instead of a map file
+    --cell-blocks <N>      With --seed, how many rows of cells [default: 3]
+    --cells-per-block <N>  With --seed, how many cells in each row [default: 4]
+    --corridor-width <N>   With --seed, how wide corridors are in cells [default: 3]
+    --exits <N>            With --seed, how many exits [default: 1]
+    --wire-density <D>     With --seed, how much of each corridor has wire, 0 to 1 [default: 0.5]
+    --difficulty <LEVEL>   easy, normal or hard [default: normal]
+    --width <PIXELS>       Window width [default: 1920]
+    --height <PIXELS>      Window height [default: 1080]
+    --fullscreen           Borderless fullscreen instead of a window
+    --headless <TICKS>     Run the game without a window for this many fixed steps, then exit
+    --record <FILE>        Save the keys pressed each step to a file when exiting
+    --replay <FILE>        Press keys from a file saved with --record
+    --log <LEVEL>          error, warn, info, debug or trace [default: debug]
+    -h, --help             Print this and exit
+
+`solo` is the same as `--state game` and `editor` the same as `--state editor`.
+
+With --headless, --record or --replay the game runs one step per frame instead of following the
+clock, so runs with the same keys step the same way.
+
+`render` draws a map to a PNG without opening a window or needing a GPU. `--scale` shrinks it,
+e.g. 0.25 for thumbnails [default: 1]. Sprites are read from `--assets` [default: assets].
+
+`convert` changes a map's format, picked from each file's extension: .txt for text maps, .bin
+for binary maps, and JSON for anything else. It also imports maps made in Tiled, from .tmx or .tmj
+files. Every format can be played and opened in the editor directly too.
+";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub state: AppState,
+    pub map: Option<PathBuf>,
+    pub seed: Option<u64>,
+    /// How to generate the prison when there's a seed.
+    pub generator: GeneratorParams,
+    pub difficulty: Difficulty,
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+    pub headless_ticks: Option<u32>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub log_level: bevy::log::Level,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            state: AppState::MainMenu,
+            map: None,
+            seed: None,
+            generator: GeneratorParams::default(),
+            difficulty: Difficulty::Normal,
+            width: 1920.0,
+            height: 1080.0,
+            fullscreen: false,
+            headless_ticks: None,
+            record: None,
+            replay: None,
+            log_level: bevy::log::Level::DEBUG,
+        }
+    }
+}
+
+/// What the command line asked for.
+#[derive(Debug)]
+pub enum Command {
+    Run(Options),
+    Render {
+        map: PathBuf,
+        out: PathBuf,
+        scale: f32,
+        assets: PathBuf,
+    },
+    Convert {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Help,
+}
+
+/// Parses the arguments after the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut state_set = false;
+    let mut generator_set = None;
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(|a| a.as_str()) == Some("render") {
+        args.next();
+        return parse_render(args);
+    }
+    if args.peek().map(|a| a.as_str()) == Some("convert") {
+        args.next();
+        let paths: Vec<String> = args.collect();
+        if paths.iter().any(|p| p == "-h" || p == "--help") {
+            return Ok(Command::Help);
+        }
+        return match <[String; 2]>::try_from(paths) {
+            Ok([from, to]) => Ok(Command::Convert {
+                from: from.into(),
+                to: to.into(),
+            }),
+            Err(_) => Err("convert needs a map file to read and one to write.".into()),
+        };
+    }
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value.", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "solo" | "editor" | "--state" => {
+                if state_set {
+                    return Err("The starting state was given more than once.".into());
+                }
+                state_set = true;
+                options.state = match arg.as_str() {
+                    "solo" => AppState::InGame,
+                    "editor" => AppState::Editor,
+                    _ => parse_state(&value("--state")?)?,
+                };
+            }
+            "--map" => options.map = Some(value("--map")?.into()),
+            "--seed" => options.seed = Some(parse_number("--seed", &value("--seed")?)?),
+            "--cell-blocks" => {
+                options.generator.cell_blocks = parse_number(&arg, &value(&arg)?)?;
+                generator_set = Some(arg);
+            }
+            "--cells-per-block" => {
+                options.generator.cells_per_block = parse_number(&arg, &value(&arg)?)?;
+                generator_set = Some(arg);
+            }
+            "--corridor-width" => {
+                options.generator.corridor_width = parse_number(&arg, &value(&arg)?)?;
+                generator_set = Some(arg);
+            }
+            "--exits" => {
+                options.generator.exits = parse_number(&arg, &value(&arg)?)?;
+                generator_set = Some(arg);
+            }
+            "--wire-density" => {
+                let density = value(&arg)?;
+                options.generator.wire_density = density
+                    .parse()
+                    .map_err(|_| format!("{} expects a number, not {:?}.", arg, density))?;
+                generator_set = Some(arg);
+            }
+            "--difficulty" => options.difficulty = parse_difficulty(&value("--difficulty")?)?,
+            "--width" => options.width = parse_size("--width", &value("--width")?)?,
+            "--height" => options.height = parse_size("--height", &value("--height")?)?,
+            "--fullscreen" => options.fullscreen = true,
+            "--headless" => {
+                options.headless_ticks = Some(parse_number("--headless", &value("--headless")?)?)
+            }
+            "--record" => options.record = Some(value("--record")?.into()),
+            "--replay" => options.replay = Some(value("--replay")?.into()),
+            "--log" => options.log_level = parse_log_level(&value("--log")?)?,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    if options.map.is_some() && options.seed.is_some() {
+        return Err("--map and --seed can't be used together.".into());
+    }
+    if let (Some(name), None) = (generator_set, options.seed) {
+        return Err(format!(
+            "{} only changes generated prisons, so it needs --seed.",
+            name
+        ));
+    }
+    if options.headless_ticks.is_some() {
+        if state_set && options.state != AppState::InGame {
+            return Err("--headless only runs the game.".into());
+        }
+        options.state = AppState::InGame;
+    }
+    Ok(Command::Run(options))
+}
+
+fn parse_render(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut paths = vec![];
+    let mut scale = 1.0;
+    let mut assets = PathBuf::from("assets");
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--scale" => {
+                let value = args.next().ok_or("--scale needs a value.")?;
+                scale = value
+                    .parse()
+                    .map_err(|_| format!("--scale expects a number, not {:?}.", value))?;
+            }
+            "--assets" => assets = args.next().ok_or("--assets needs a value.")?.into(),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument: {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([map, out]) => Ok(Command::Render {
+            map,
+            out,
+            scale,
+            assets,
+        }),
+        Err(_) => Err("render needs a map file and a PNG file to write.".into()),
+    }
+}
+
+fn parse_state(s: &str) -> Result<AppState, String> {
+    match s {
+        "splash" => Ok(AppState::Splash),
+        "menu" => Ok(AppState::MainMenu),
+        "game" => Ok(AppState::InGame),
+        "editor" => Ok(AppState::Editor),
+        _ => Err(format!(
+            "Unknown state {:?}, expected splash, menu, game or editor.",
+            s
+        )),
+    }
+}
+
+fn parse_difficulty(s: &str) -> Result<Difficulty, String> {
+    match s.to_lowercase().as_str() {
+        "easy" => Ok(Difficulty::Easy),
+        "normal" => Ok(Difficulty::Normal),
+        "hard" => Ok(Difficulty::Hard),
+        _ => Err(format!(
+            "Unknown difficulty {:?}, expected easy, normal or hard.",
+            s
+        )),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, s: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("{} expects a whole number, not {:?}.", name, s))
+}
+
+fn parse_size(name: &str, s: &str) -> Result<f32, String> {
+    let size: u32 = parse_number(name, s)?;
+    if size == 0 {
+        return Err(format!("{} can't be zero.", name));
+    }
+    Ok(size as f32)
+}
+
+fn parse_log_level(s: &str) -> Result<bevy::log::Level, String> {
+    match s.to_lowercase().as_str() {
+        "error" => Ok(bevy::log::Level::ERROR),
+        "warn" => Ok(bevy::log::Level::WARN),
+        "info" => Ok(bevy::log::Level::INFO),
+        "debug" => Ok(bevy::log::Level::DEBUG),
+        "trace" => Ok(bevy::log::Level::TRACE),
+        _ => Err(format!(
+            "Unknown log level {:?}, expected error, warn, info, debug or trace.",
+            s
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    fn options(args: &str) -> Options {
+        match parse_args(args) {
+            Ok(Command::Run(options)) => options,
+            other => panic!("{:?} gave {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn defaults_to_the_menu() {
+        let o = options("");
+        assert_eq!(o.state, AppState::MainMenu);
+        assert_eq!(o.map, None);
+        assert_eq!(o.headless_ticks, None);
+    }
+
+    #[test]
+    fn starting_states() {
+        assert_eq!(options("solo").state, AppState::InGame);
+        assert_eq!(options("editor").state, AppState::Editor);
+        assert_eq!(options("--state splash").state, AppState::Splash);
+        assert!(parse_args("--state game solo").is_err());
+        assert!(parse_args("--state nowhere").is_err());
+    }
+
+    #[test]
+    fn values() {
+        let o = options("--map a.json --width 800 --height 600 --fullscreen --log warn");
+        assert_eq!(o.map, Some(PathBuf::from("a.json")));
+        assert_eq!((o.width, o.height), (800.0, 600.0));
+        assert!(o.fullscreen);
+        assert_eq!(o.log_level, bevy::log::Level::WARN);
+        assert_eq!(options("--seed 7").seed, Some(7));
+        assert_eq!(options("").difficulty, Difficulty::Normal);
+        assert_eq!(options("--difficulty Hard").difficulty, Difficulty::Hard);
+    }
+
+    #[test]
+    fn bad_values() {
+        assert!(parse_args("--map").is_err());
+        assert!(parse_args("--width 0").is_err());
+        assert!(parse_args("--height tall").is_err());
+        assert!(parse_args("--log loud").is_err());
+        assert!(parse_args("--difficulty impossible").is_err());
+        assert!(parse_args("--map a.json --seed 1").is_err());
+        assert!(parse_args("--what").is_err());
+    }
+
+    #[test]
+    fn generator_values() {
+        let o = options(concat!(
+            "--seed 1 --cell-blocks 2 --cells-per-block 5 --corridor-width 4 --exits 2 ",
+            "--wire-density 0.25"
+        ));
+        assert_eq!(o.generator.cell_blocks, 2);
+        assert_eq!(o.generator.cells_per_block, 5);
+        assert_eq!(o.generator.corridor_width, 4);
+        assert_eq!(o.generator.exits, 2);
+        assert_eq!(o.generator.wire_density, 0.25);
+        assert!(parse_args("--exits 2").is_err());
+        assert!(parse_args("--seed 1 --cell-blocks many").is_err());
+        assert!(parse_args("--seed 1 --wire-density lots").is_err());
+    }
+
+    #[test]
+    fn headless_runs_the_game() {
+        let o = options("--headless 100");
+        assert_eq!(o.headless_ticks, Some(100));
+        assert_eq!(o.state, AppState::InGame);
+        assert!(parse_args("--headless 100 editor").is_err());
+        assert!(parse_args("--headless -1").is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        assert!(matches!(parse_args("--help"), Ok(Command::Help)));
+        assert!(matches!(
+            parse_args("render a.json a.png --scale 0.5"),
+            Ok(Command::Render { scale, .. }) if scale == 0.5
+        ));
+        assert!(parse_args("render a.json").is_err());
+        assert!(matches!(
+            parse_args("convert a.json a.txt"),
+            Ok(Command::Convert { .. })
+        ));
+        assert!(parse_args("convert a.json").is_err());
+        assert!(matches!(parse_args("convert --help"), Ok(Command::Help)));
+    }
+}
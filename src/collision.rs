@@ -0,0 +1,240 @@
+use crate::position::{GridPosition, Position, Velocity};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use nalgebra::Vector2;
+
+/// A circle used for character vs character collisions. Radius is in grid units.
+///
+/// Bodies that aren't `pushable` will never be moved by another body, which lets the warden
+/// stand in a doorway and block it.
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub radius: f64,
+    pub pushable: bool,
+}
+
+impl Body {
+    pub fn warden() -> Self {
+        Self {
+            radius: 0.3,
+            pushable: false,
+        }
+    }
+
+    pub fn prisoner() -> Self {
+        Self {
+            radius: 0.3,
+            pushable: true,
+        }
+    }
+}
+
+/// Buckets entities by the cell they're in, so we only need to check neighbouring cells for
+/// collisions instead of every pair.
+///
+/// This only works while every body's diameter is smaller than a cell.
+#[derive(Debug, Default)]
+pub struct SpatialHash {
+    cells: HashMap<GridPosition, Vec<Entity>>,
+}
+
+impl SpatialHash {
+    pub fn clear(&mut self) {
+        // Keep the allocations around for the next frame.
+        for entities in self.cells.values_mut() {
+            entities.clear();
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, pos: &Position) {
+        self.cells
+            .entry(pos.nearest_cell())
+            .or_default()
+            .push(entity);
+    }
+
+    /// All entities in the same cell as `pos` and the eight cells around it.
+    pub fn nearby(&self, pos: &Position) -> Vec<Entity> {
+        let cell = pos.nearest_cell();
+        let mut found = vec![];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let neighbour = &cell + &GridPosition::new(x, y);
+                if let Some(entities) = self.cells.get(&neighbour) {
+                    found.extend(entities.iter().cloned());
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Adjust velocities so that bodies don't overlap after velocities are applied.
+///
+/// This runs before `check_velocity_collisions`, so a prisoner can't be pushed into a wall.
+pub fn check_body_collisions(
+    mut hash: Local<SpatialHash>,
+    mut query: Query<(Entity, &Position, &mut Velocity, &Body)>,
+) {
+    hash.clear();
+    let mut bodies: HashMap<Entity, (Vector2<f64>, Body)> = HashMap::default();
+    for (entity, pos, vel, body) in query.iter_mut() {
+        let next = pos.0 + vel.0;
+        hash.insert(entity, &Position::from(next));
+        bodies.insert(entity, (next, body.clone()));
+    }
+
+    let mut corrections: HashMap<Entity, Vector2<f64>> = HashMap::default();
+    for (entity, (next, body)) in &bodies {
+        for other in hash.nearby(&Position::from(*next)) {
+            // Only resolve each pair once.
+            if other.id() <= entity.id() {
+                continue;
+            }
+            let (other_next, other_body) = &bodies[&other];
+
+            let diff = other_next - next;
+            let dist = diff.magnitude();
+            let min_dist = body.radius + other_body.radius;
+            if dist >= min_dist {
+                continue;
+            }
+
+            // Two bodies in the exact same spot, push them apart in any direction.
+            let normal = if dist > 0.0001 {
+                diff / dist
+            } else {
+                Vector2::new(1.0, 0.0)
+            };
+            let overlap = min_dist - dist;
+            let share = match (body.pushable, other_body.pushable) {
+                (true, false) => 1.0,
+                (false, true) => 0.0,
+                _ => 0.5,
+            };
+
+            *corrections.entry(*entity).or_insert_with(Vector2::zeros) -=
+                normal * overlap * share;
+            *corrections.entry(other).or_insert_with(Vector2::zeros) +=
+                normal * overlap * (1.0 - share);
+        }
+    }
+
+    for (entity, correction) in corrections {
+        if let Ok((_, _, mut vel, body)) = query.get_mut(entity) {
+            if !body.pushable {
+                continue;
+            }
+            vel.0 += correction;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f64, y: f64) -> Position {
+        Position(Vector2::new(x, y))
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort_by_key(|e| e.id());
+        entities
+    }
+
+    #[test]
+    fn nearby_crosses_cell_borders() {
+        let mut hash = SpatialHash::default();
+        // Cell (0, 0), just short of the border with (1, 0).
+        let a = Entity::new(0);
+        hash.insert(a, &at(0.45, 0.0));
+        // Just over the border in cell (1, 0).
+        let b = Entity::new(1);
+        hash.insert(b, &at(0.55, 0.0));
+        // Diagonally across the corner in cell (-1, -1).
+        let c = Entity::new(2);
+        hash.insert(c, &at(-0.55, -0.55));
+        // Cell (2, 0), two cells from `a` even though it's only 1.15 away.
+        let d = Entity::new(3);
+        hash.insert(d, &at(1.6, 0.0));
+
+        assert_eq!(sorted(hash.nearby(&at(0.45, 0.0))), vec![a, b, c]);
+        assert_eq!(sorted(hash.nearby(&at(0.55, 0.0))), vec![a, b, d]);
+        assert_eq!(sorted(hash.nearby(&at(-0.55, -0.55))), vec![a, c]);
+        assert!(hash.nearby(&at(5.0, 5.0)).is_empty());
+    }
+
+    /// Runs `check_body_collisions` once on bodies spawned in order, returning their velocities.
+    fn collide(bodies: Vec<(Position, Velocity, Body)>) -> Vec<Vector2<f64>> {
+        let mut world = World::default();
+        let entities: Vec<Entity> = bodies
+            .into_iter()
+            .map(|(pos, vel, body)| world.spawn().insert_bundle((pos, vel, body)).id())
+            .collect();
+        let mut stage = SystemStage::single(check_body_collisions.system());
+        stage.run(&mut world);
+        entities
+            .iter()
+            .map(|e| world.get::<Velocity>(*e).unwrap().0)
+            .collect()
+    }
+
+    fn assert_near(actual: Vector2<f64>, expected: Vector2<f64>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-9,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn overlapping_prisoners_split_the_correction() {
+        let velocities = collide(vec![
+            (at(0.0, 0.0), Velocity::zero(), Body::prisoner()),
+            (at(0.4, 0.0), Velocity::zero(), Body::prisoner()),
+        ]);
+        // 0.2 of overlap, so each moves 0.1 away from the other.
+        assert_near(velocities[0], Vector2::new(-0.1, 0.0));
+        assert_near(velocities[1], Vector2::new(0.1, 0.0));
+    }
+
+    #[test]
+    fn prisoners_take_the_whole_correction_from_wardens() {
+        // Either way round, since only the first of each pair is checked against the second.
+        for warden_first in [false, true].iter() {
+            let prisoner = (at(0.0, 0.0), Velocity::zero(), Body::prisoner());
+            // Moving into the prisoner, ending up 0.4 away.
+            let warden = (at(0.4, -0.1), Velocity::new(0.0, 0.1), Body::warden());
+            let velocities = if *warden_first {
+                let v = collide(vec![warden, prisoner]);
+                vec![v[1], v[0]]
+            } else {
+                collide(vec![prisoner, warden])
+            };
+            assert_near(velocities[0], Vector2::new(-0.2, 0.0));
+            assert_near(velocities[1], Vector2::new(0.0, 0.1));
+        }
+    }
+
+    #[test]
+    fn bodies_in_the_same_spot_are_separated() {
+        let velocities = collide(vec![
+            (at(2.0, 2.0), Velocity::zero(), Body::prisoner()),
+            (at(2.0, 2.0), Velocity::zero(), Body::prisoner()),
+        ]);
+        let apart = (at(2.0, 2.0).0 + velocities[1]) - (at(2.0, 2.0).0 + velocities[0]);
+        assert!(apart.magnitude() >= 0.6 - 1e-9, "only {:?} apart", apart);
+    }
+
+    #[test]
+    fn clear_empties_every_cell() {
+        let mut hash = SpatialHash::default();
+        hash.insert(Entity::new(0), &at(0.0, 0.0));
+        hash.insert(Entity::new(1), &at(1.0, 1.0));
+        hash.clear();
+        assert!(hash.nearby(&at(0.0, 0.0)).is_empty());
+        assert!(hash.nearby(&at(1.0, 1.0)).is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::game::{Door, GRID_SIZE};
+use crate::map::{Item, ItemInfo, Map, PathfindingMap};
+use crate::path::Path;
+use crate::position::{GridPosition, Position};
+use bevy::prelude::*;
+
+/// Key to toggle the pathfinding overlay, in both the game and the editor.
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+/// The overlay sits above the map but below the editor selection.
+const CELL_Z: f32 = 4.0;
+const PATH_Z: f32 = 4.5;
+
+/// Shows walkable and blocked cells, door states and the path of each agent.
+#[derive(Debug, Default)]
+pub struct DebugOverlay(pub bool);
+
+/// A walkable, blocked or door cell. Only redrawn when the map changes.
+pub struct OverlayCell;
+
+/// Part of a path. Redrawn every frame since agents move along them.
+pub struct OverlayPath;
+
+pub struct OverlayMaterials {
+    walkable: Handle<ColorMaterial>,
+    blocked: Handle<ColorMaterial>,
+    door_open: Handle<ColorMaterial>,
+    door_closed: Handle<ColorMaterial>,
+    path: Handle<ColorMaterial>,
+    target: Handle<ColorMaterial>,
+}
+
+impl FromWorld for OverlayMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            walkable: materials.add(Color::rgba(0.0, 1.0, 0.0, 0.15).into()),
+            blocked: materials.add(Color::rgba(1.0, 0.0, 0.0, 0.3).into()),
+            door_open: materials.add(Color::rgba(0.0, 0.5, 1.0, 0.4).into()),
+            door_closed: materials.add(Color::rgba(1.0, 0.5, 0.0, 0.5).into()),
+            path: materials.add(Color::rgba(1.0, 1.0, 0.0, 0.6).into()),
+            target: materials.add(Color::rgba(1.0, 0.0, 1.0, 0.8).into()),
+        }
+    }
+}
+
+pub fn toggle_overlay(keys: Res<Input<KeyCode>>, mut overlay: ResMut<DebugOverlay>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+pub fn draw_game_overlay(
+    mut commands: Commands,
+    overlay: Res<DebugOverlay>,
+    materials: Res<OverlayMaterials>,
+    pathfinding_map: Res<PathfindingMap>,
+    paths: Query<&Path>,
+    doors: Query<(&GridPosition, &ItemInfo), With<Door>>,
+    cells: Query<Entity, With<OverlayCell>>,
+    path_cells: Query<Entity, With<OverlayPath>>,
+) {
+    despawn_all(&mut commands, path_cells.iter());
+    if !overlay.0 {
+        despawn_all(&mut commands, cells.iter());
+        return;
+    }
+
+    // Opening or closing a door also changes the pathfinding map.
+    if overlay.is_changed() || pathfinding_map.is_changed() {
+        despawn_all(&mut commands, cells.iter());
+        draw_cells(&mut commands, &materials, &pathfinding_map);
+
+        for (grid_pos, item_info) in doors.iter() {
+            for delta in &item_info.shape().0 {
+                let cell = grid_pos + delta;
+                let material = if pathfinding_map.is_walkable_cell(&cell) {
+                    &materials.door_open
+                } else {
+                    &materials.door_closed
+                };
+                spawn_marker(&mut commands, material, &cell, 0.9, CELL_Z + 0.1)
+                    .insert(OverlayCell);
+            }
+        }
+    }
+
+    for path in paths.iter() {
+        draw_path(&mut commands, &materials, path);
+    }
+}
+
+pub fn draw_editor_overlay(
+    mut commands: Commands,
+    overlay: Res<DebugOverlay>,
+    materials: Res<OverlayMaterials>,
+    map: Res<Map>,
+    cells: Query<Entity, With<OverlayCell>>,
+) {
+    if !overlay.0 {
+        despawn_all(&mut commands, cells.iter());
+        return;
+    }
+    if !overlay.is_changed() && !map.is_changed() {
+        return;
+    }
+
+    despawn_all(&mut commands, cells.iter());
+    let pathfinding_map = PathfindingMap::from_map(&map);
+    draw_cells(&mut commands, &materials, &pathfinding_map);
+
+    // Doors are always closed in the editor, but it's still useful to see what cells they cover.
+    for item_info in map.items.iter().filter(|i| i.item == Item::Door) {
+        let grid_pos = item_info.position.nearest_cell_grid_pos();
+        for delta in &item_info.shape().0 {
+            let cell = &grid_pos + delta;
+            spawn_marker(&mut commands, &materials.door_closed, &cell, 0.9, CELL_Z + 0.1)
+                .insert(OverlayCell);
+        }
+    }
+}
+
+fn draw_cells(
+    commands: &mut Commands,
+    materials: &OverlayMaterials,
+    pathfinding_map: &PathfindingMap,
+) {
+    for (cell, walkable) in pathfinding_map.walkable_cells.iter() {
+        let material = if *walkable {
+            &materials.walkable
+        } else {
+            &materials.blocked
+        };
+        spawn_marker(commands, material, cell, 0.9, CELL_Z).insert(OverlayCell);
+    }
+}
+
+fn draw_path(commands: &mut Commands, materials: &OverlayMaterials, path: &Path) {
+    for cell in path.remaining().iter().skip(1) {
+        spawn_marker(commands, &materials.path, cell, 0.3, PATH_Z).insert(OverlayPath);
+    }
+    spawn_marker(commands, &materials.target, path.target(), 0.5, PATH_Z + 0.1)
+        .insert(OverlayPath);
+}
+
+/// A coloured square in a cell. `size` is the fraction of a cell.
+fn spawn_marker<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    material: &Handle<ColorMaterial>,
+    cell: &GridPosition,
+    size: f32,
+    z: f32,
+) -> bevy::ecs::system::EntityCommands<'a, 'b> {
+    let mut transform = Position::from(cell).to_transform();
+    transform.translation.z = z;
+    commands.spawn_bundle(SpriteBundle {
+        material: material.clone(),
+        sprite: Sprite::new(Vec2::splat(GRID_SIZE * size)),
+        transform,
+        visible: Visible {
+            is_visible: true,
+            is_transparent: true,
+        },
+        ..Default::default()
+    })
+}
+
+fn despawn_all(commands: &mut Commands, entities: impl Iterator<Item = Entity>) {
+    for entity in entities {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,363 @@
+use super::files::{FileAction, FileRequest};
+use super::tools::{rect_cells, room_items};
+use super::UiFilename;
+use crate::ascii;
+use crate::autotile::autotile;
+use crate::map::{self, Item, ItemInfo, Map};
+use crate::position::{FlexPosition, GridPosition};
+use crate::tiled;
+use bevy::prelude::*;
+use bevy_egui::egui::Ui;
+use bevy_egui::{egui, EguiContext};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MAPS_DIR: &str = "assets/maps";
+
+/// Ends of file names in `MAPS_DIR` that aren't maps.
+const NOT_MAPS: [&str; 3] = [".balance.json", ".recovery.json", ".tmp"];
+
+#[derive(Debug, Clone)]
+pub struct MapSummary {
+    pub items: usize,
+    pub walls: usize,
+    pub doors: usize,
+    pub prisoners: usize,
+    pub wardens: usize,
+    pub exits: usize,
+    /// Width and height in cells.
+    pub size: (i32, i32),
+}
+
+impl MapSummary {
+    pub fn new(map: &Map) -> Self {
+        let count = |f: &dyn Fn(&Item) -> bool| map.items.iter().filter(|i| f(&i.item)).count();
+        let size = match map.bounds() {
+            Some((min, max)) => (max.0.x - min.0.x + 1, max.0.y - min.0.y + 1),
+            None => (0, 0),
+        };
+        Self {
+            items: map.items.len(),
+            walls: count(&|i| *i == Item::Wall || *i == Item::WallCorner),
+            doors: count(&|i| *i == Item::Door),
+            prisoners: count(&|i| *i == Item::Prisoner),
+            wardens: count(&|i| *i == Item::Warden),
+            exits: count(&|i| *i == Item::Exit),
+            size,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MapEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub summary: Result<MapSummary, String>,
+}
+
+/// The maps in `MAPS_DIR`. Set `stale` to read the directory again.
+#[derive(Debug)]
+pub struct MapBrowser {
+    pub entries: Vec<MapEntry>,
+    pub stale: bool,
+    selected: Option<usize>,
+}
+
+impl Default for MapBrowser {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            stale: true,
+            selected: None,
+        }
+    }
+}
+
+/// Turns a name from the filename box into a path in `MAPS_DIR`. Names ending in `.txt` are text
+/// maps and `.bin` binary maps, anything else is JSON.
+pub fn map_path(name: &str) -> PathBuf {
+    if name.ends_with(".txt") || name.ends_with(".bin") {
+        return Path::new(MAPS_DIR).join(name);
+    }
+    Path::new(MAPS_DIR).join(format!("{}.json", name))
+}
+
+pub fn list_maps(dir: &Path) -> Vec<MapEntry> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Could not list {:?}: {}", dir, e);
+            return vec![];
+        }
+    };
+
+    let mut entries: Vec<MapEntry> = read_dir
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let name = p.file_name().unwrap_or_default().to_string_lossy();
+            (name.ends_with(".json")
+                || ascii::is_ascii_path(p)
+                || map::is_binary_path(p)
+                || tiled::is_tiled_path(p))
+                && !NOT_MAPS.iter().any(|n| name.ends_with(n))
+        })
+        .map(|path| MapEntry {
+            // Text and binary maps keep their extension, so `map_path` turns the name back into this path.
+            // Tiled maps don't, so Save As writes the imported map next to them as JSON.
+            name: if ascii::is_ascii_path(&path) || map::is_binary_path(&path) {
+                path.file_name()
+            } else {
+                path.file_stem()
+            }
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+            summary: Map::load(&path).map(|m| MapSummary::new(&m)),
+            path,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+pub fn refresh_browser(mut browser: ResMut<MapBrowser>) {
+    if !browser.stale {
+        return;
+    }
+    browser.entries = list_maps(Path::new(MAPS_DIR));
+    browser.selected = None;
+    browser.stale = false;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    CellBlock,
+    Corridor,
+    Yard,
+}
+
+impl Template {
+    pub fn all() -> [Template; 3] {
+        [Template::CellBlock, Template::Corridor, Template::Yard]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::CellBlock => "Empty cell block",
+            Template::Corridor => "Corridor",
+            Template::Yard => "Yard",
+        }
+    }
+
+    pub fn map(&self) -> Map {
+        let mut map = match self {
+            Template::CellBlock => cell_block(),
+            Template::Corridor => corridor(),
+            Template::Yard => yard(),
+        };
+        autotile(&mut map);
+        map
+    }
+}
+
+fn at(item: Item, x: i32, y: i32, rotation: f32) -> ItemInfo {
+    ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), rotation)
+}
+
+fn floor(item: Item, min: (i32, i32), max: (i32, i32)) -> Vec<ItemInfo> {
+    rect_cells(
+        &GridPosition::new(min.0, min.1),
+        &GridPosition::new(max.0, max.1),
+        true,
+    )
+    .iter()
+    .map(|c| at(item.clone(), c.0.x, c.0.y, 0.0))
+    .collect()
+}
+
+fn room(min: (i32, i32), max: (i32, i32)) -> Vec<ItemInfo> {
+    room_items(
+        &GridPosition::new(min.0, min.1),
+        &GridPosition::new(max.0, max.1),
+    )
+}
+
+/// Removes walls where a door goes, then adds the door.
+fn door(items: &mut Vec<ItemInfo>, x: i32, y: i32, rotation: f32) {
+    let door = at(Item::Door, x, y, rotation);
+    let cells = door.cells();
+    items.retain(|i| {
+        let wall = i.item == Item::Wall || i.item == Item::WallCorner;
+        !(wall && cells.contains(&i.position.nearest_cell_grid_pos()))
+    });
+    items.push(door);
+}
+
+/// Four cells along the bottom of a corridor, with a door into each, and the warden in the
+/// corridor.
+fn cell_block() -> Map {
+    let mut items = floor(Item::GeneralTile, (1, 8), (35, 14));
+    items.extend(room((0, 0), (36, 15)));
+    for i in 0..4 {
+        let x = i * 9;
+        items.extend(floor(Item::CellTile, (x + 1, 1), (x + 8, 6)));
+        if i > 0 {
+            // Up to and including the front wall's row, so it joins it.
+            for y in 1..=7 {
+                items.push(at(Item::Wall, x, y, 90.0));
+            }
+        }
+        for x in (x + 1)..(x + 9) {
+            items.push(at(Item::Wall, x, 7, 0.0));
+        }
+        items.push(at(Item::Prisoner, x + 4, 3, 0.0));
+    }
+    for i in 0..4 {
+        door(&mut items, i * 9 + 4, 7, 0.0);
+    }
+    items.push(at(Item::Warden, 18, 11, 0.0));
+    items.push(at(Item::Exit, 35, 11, 0.0));
+    Map::from_items(items)
+}
+
+fn corridor() -> Map {
+    let mut items = floor(Item::GeneralTile, (1, 1), (29, 5));
+    items.extend(room((0, 0), (30, 6)));
+    items.push(at(Item::Warden, 3, 3, 0.0));
+    items.push(at(Item::Prisoner, 15, 3, 0.0));
+    items.push(at(Item::Exit, 29, 3, 0.0));
+    Map::from_items(items)
+}
+
+fn yard() -> Map {
+    let mut items = floor(Item::GeneralTile, (1, 1), (19, 19));
+    items.extend(room((0, 0), (20, 20)));
+    items.push(at(Item::Warden, 10, 10, 0.0));
+    items.push(at(Item::Prisoner, 10, 5, 0.0));
+    items.push(at(Item::Exit, 19, 10, 0.0));
+    Map::from_items(items)
+}
+
+pub fn browser_ui(
+    egui_context: ResMut<EguiContext>,
+    mut browser: ResMut<MapBrowser>,
+    mut ui_filename: ResMut<UiFilename>,
+    mut file_requests: EventWriter<FileRequest>,
+) {
+    egui::Window::new("Maps")
+        .default_width(250.0)
+        .default_pos([10.0, 600.0])
+        .show(egui_context.ctx(), |ui| {
+            if ui.button("Refresh").clicked() {
+                browser.stale = true;
+            }
+
+            egui::ScrollArea::from_max_height(200.0).show(ui, |ui| {
+                for idx in 0..browser.entries.len() {
+                    let entry = &browser.entries[idx];
+                    let label = ui.selectable_label(browser.selected == Some(idx), &entry.name);
+                    if label.double_clicked() {
+                        file_requests.send(FileRequest::new(FileAction::Load(entry.path.clone())));
+                    }
+                    if label.clicked() {
+                        ui_filename.0 = entry.name.clone();
+                        browser.selected = Some(idx);
+                    }
+                }
+            });
+
+            if let Some(entry) = browser.selected.and_then(|i| browser.entries.get(i)) {
+                ui.separator();
+                summary(ui, entry);
+                if ui.button("Load").clicked() {
+                    file_requests.send(FileRequest::new(FileAction::Load(entry.path.clone())));
+                }
+            }
+
+            ui.separator();
+            ui.label("New from template:");
+            ui.horizontal_wrapped(|ui| {
+                for template in Template::all() {
+                    if ui.button(template.name()).clicked() {
+                        file_requests.send(FileRequest::new(FileAction::NewFromTemplate(template)));
+                    }
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Name:");
+                ui.text_edit_singleline(&mut ui_filename.0);
+            });
+            ui.horizontal(|ui| {
+                let path = map_path(&ui_filename.0);
+                if ui.button("Save As").clicked() {
+                    file_requests.send(FileRequest::new(FileAction::Save(path.clone())));
+                }
+                if ui.button("Rename").clicked() {
+                    file_requests.send(FileRequest::new(FileAction::Rename(path)));
+                }
+            });
+        });
+}
+
+fn summary(ui: &mut Ui, entry: &MapEntry) {
+    ui.heading(&entry.name);
+    match &entry.summary {
+        Ok(s) => {
+            ui.label(format!(
+                "{} x {} cells, {} items",
+                s.size.0, s.size.1, s.items
+            ));
+            ui.label(format!(
+                "{} walls, {} doors, {} exits",
+                s.walls, s.doors, s.exits
+            ));
+            ui.label(format!("{} prisoners, {} wardens", s.prisoners, s.wardens));
+        }
+        Err(e) => {
+            ui.colored_label(egui::Color32::RED, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint;
+
+    #[test]
+    fn templates_pass_lint() {
+        for template in Template::all().iter() {
+            let diagnostics: Vec<String> = lint(&template.map())
+                .iter()
+                .map(|d| d.to_string())
+                .collect();
+            assert!(
+                diagnostics.is_empty(),
+                "{}: {:?}",
+                template.name(),
+                diagnostics
+            );
+        }
+    }
+
+    #[test]
+    fn cell_block_front_wall_has_no_gaps() {
+        let map = cell_block();
+        let cells: Vec<GridPosition> = map
+            .items
+            .iter()
+            .filter(|i| i.item.layer() == Item::Wall.layer())
+            .flat_map(|i| i.cells())
+            .collect();
+        for x in 0..=36 {
+            assert!(
+                cells.contains(&GridPosition::new(x, 7)),
+                "Nothing at ({}, 7)",
+                x
+            );
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use super::{EditorSettings, Selection};
+use crate::game::GRID_SIZE;
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy_egui::EguiContext;
+
+pub const DEFAULT_ZOOM: f32 = 8.0;
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 64.0;
+/// How much one notch of the mouse wheel zooms by.
+const ZOOM_STEP: f32 = 1.15;
+/// In screen pixels per second, so panning feels the same at any zoom.
+const PAN_SPEED: f32 = 600.0;
+const GRID_Z: f32 = 3.5;
+/// Past this many lines the grid is too dense to be useful, so it isn't drawn.
+const MAX_GRID_LINES: i32 = 400;
+
+/// Where the cursor is in the world, or `None` if it's outside the window.
+pub fn cursor_world_pos(window: &Window, camera_transform: &Transform) -> Option<Vec2> {
+    let pos = window.cursor_position()?;
+    let size = Vec2::new(window.width(), window.height());
+    let p = pos - size / 2.0;
+    let world_pos = camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
+    Some(world_pos.truncate().truncate())
+}
+
+/// Zooms towards the cursor, so the point under it stays put.
+pub fn zoom_camera(
+    mut wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    mut cameras: Query<&mut Transform, With<Camera>>,
+    egui_context: Res<EguiContext>,
+) {
+    let notches: f32 = wheel
+        .iter()
+        .map(|e| match e.unit {
+            MouseScrollUnit::Line => e.y,
+            // Touchpads scroll in pixels.
+            MouseScrollUnit::Pixel => e.y / 40.0,
+        })
+        .sum();
+    if notches == 0.0 || egui_context.ctx().is_pointer_over_area() {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let mut transform = cameras.single_mut().unwrap();
+    let before = match cursor_world_pos(window, &transform) {
+        Some(p) => p,
+        None => return,
+    };
+
+    // Scrolling up zooms in, which is a smaller scale.
+    let zoom = (transform.scale.x * ZOOM_STEP.powf(-notches)).clamp(MIN_ZOOM, MAX_ZOOM);
+    transform.scale = Vec3::new(zoom, zoom, 1.0);
+
+    let after = cursor_world_pos(window, &transform).unwrap_or(before);
+    transform.translation += (before - after).extend(0.0);
+}
+
+/// Arrow keys or WASD. Ignored while typing, and while Ctrl is held so shortcuts don't pan.
+pub fn pan_keys(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut cameras: Query<&mut Transform, With<Camera>>,
+    egui_context: Res<EguiContext>,
+) {
+    if egui_context.ctx().wants_keyboard_input()
+        || keys.pressed(KeyCode::LControl)
+        || keys.pressed(KeyCode::RControl)
+    {
+        return;
+    }
+
+    let mut direction = Vec2::default();
+    if keys.pressed(KeyCode::Left) || keys.pressed(KeyCode::A) {
+        direction.x -= 1.0;
+    }
+    if keys.pressed(KeyCode::Right) || keys.pressed(KeyCode::D) {
+        direction.x += 1.0;
+    }
+    if keys.pressed(KeyCode::Down) || keys.pressed(KeyCode::S) {
+        direction.y -= 1.0;
+    }
+    if keys.pressed(KeyCode::Up) || keys.pressed(KeyCode::W) {
+        direction.y += 1.0;
+    }
+    if direction == Vec2::default() {
+        return;
+    }
+
+    let mut transform = cameras.single_mut().unwrap();
+    let distance = PAN_SPEED * transform.scale.x * time.delta_seconds();
+    transform.translation += (direction.normalize() * distance).extend(0.0);
+}
+
+pub fn drag_diff(
+    mut last_pos: Local<Vec2>,
+    selection: Query<&Transform, With<Selection>>,
+    mut drag: ResMut<Drag>,
+) {
+    let new_pos = selection.single().unwrap();
+    let drag_amount = *last_pos - new_pos.translation.truncate();
+    *drag = Drag(drag_amount);
+    *last_pos = new_pos.translation.truncate();
+}
+
+#[derive(Default)]
+pub struct Drag(Vec2);
+
+/// Pans while the right mouse button is held.
+pub fn drag(
+    button: Res<Input<MouseButton>>,
+    mut cameras: Query<&mut Transform, With<Camera>>,
+    drag: Res<Drag>,
+) {
+    if !button.pressed(MouseButton::Right) {
+        return;
+    }
+
+    let mut pos = cameras.single_mut().unwrap();
+    pos.translation += drag.0.extend(0.0);
+}
+
+pub struct GridMaterial(Handle<ColorMaterial>);
+
+impl FromWorld for GridMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::rgba(1.0, 1.0, 1.0, 0.25).into()))
+    }
+}
+
+pub struct GridLine;
+
+/// Lines along the cell edges, covering whatever the camera can see. The lines are kept between
+/// frames and moved into place, so panning and zooming only spawn more when more fit on screen.
+pub fn draw_grid(
+    mut commands: Commands,
+    settings: Res<EditorSettings>,
+    material: Res<GridMaterial>,
+    windows: Res<Windows>,
+    cameras: Query<(&Transform, ChangeTrackers<Transform>), With<Camera>>,
+    mut lines: Query<
+        (&mut Transform, &mut Sprite, &mut Visible),
+        (With<GridLine>, Without<Camera>),
+    >,
+) {
+    let (camera, camera_tracker) = cameras.single().unwrap();
+    if !settings.is_changed() && !camera_tracker.is_changed() {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let scale = camera.scale.x;
+    let half = Vec2::new(window.width(), window.height()) * scale / 2.0;
+    let centre = camera.translation.truncate();
+    let min = ((centre - half) / GRID_SIZE).floor();
+    let max = ((centre + half) / GRID_SIZE).ceil();
+    let (min_x, max_x, min_y, max_y) = (min.x as i32, max.x as i32, min.y as i32, max.y as i32);
+
+    // Each line as its centre and size.
+    let mut wanted: Vec<(Vec2, Vec2)> = vec![];
+    if settings.grid && (max_x - min_x) + (max_y - min_y) <= MAX_GRID_LINES {
+        // Cells are centred on multiples of GRID_SIZE, so their edges are half a cell off.
+        let thickness = scale;
+        for x in min_x..=max_x {
+            let x = (x as f32 - 0.5) * GRID_SIZE;
+            wanted.push((Vec2::new(x, centre.y), Vec2::new(thickness, half.y * 2.0)));
+        }
+        for y in min_y..=max_y {
+            let y = (y as f32 - 0.5) * GRID_SIZE;
+            wanted.push((Vec2::new(centre.x, y), Vec2::new(half.x * 2.0, thickness)));
+        }
+    }
+
+    let mut wanted = wanted.into_iter();
+    for (mut transform, mut sprite, mut visible) in lines.iter_mut() {
+        match wanted.next() {
+            Some((pos, size)) => {
+                transform.translation = pos.extend(GRID_Z);
+                sprite.size = size;
+                visible.is_visible = true;
+            }
+            None => visible.is_visible = false,
+        }
+    }
+    for (pos, size) in wanted {
+        spawn_line(&mut commands, &material, pos, size);
+    }
+}
+
+fn spawn_line(commands: &mut Commands, material: &GridMaterial, pos: Vec2, size: Vec2) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: material.0.clone(),
+            sprite: Sprite::new(size),
+            transform: Transform::from_translation(pos.extend(GRID_Z)),
+            ..Default::default()
+        })
+        .insert(GridLine);
+}
+
+pub fn toggle_grid(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<EditorSettings>,
+    egui_context: Res<EguiContext>,
+) {
+    if keys.just_pressed(KeyCode::G) && !egui_context.ctx().wants_keyboard_input() {
+        settings.grid = !settings.grid;
+    }
+}
@@ -0,0 +1,497 @@
+use super::browser::{MapBrowser, Template};
+use super::history::History;
+use super::selection::SelectedItems;
+use super::{add_item, clear_map};
+use crate::balance::Balance;
+use crate::map::{ItemInfo, Map};
+use crate::scripting;
+use crate::tiled;
+use bevy::prelude::*;
+use bevy_egui::egui::Align2;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where unsaved work is periodically written, in case the editor crashes.
+pub const RECOVERY_PATH: &str = "assets/maps/.recovery.json";
+
+const AUTOSAVE_SECONDS: f32 = 30.0;
+
+/// The map being edited and whether it has changed since it was loaded or saved.
+#[derive(Debug, Default)]
+pub struct Document {
+    /// `None` for a new map that hasn't been saved yet.
+    pub path: Option<PathBuf>,
+    /// Which version of the map is showing. Each edit makes a new one, and undo and redo go back
+    /// to the one they left, so undoing back to the saved map isn't dirty.
+    pub revision: u64,
+    saved_revision: u64,
+    autosaved_revision: u64,
+    /// The highest revision handed out, so a new edit never reuses an undone one.
+    last_revision: u64,
+}
+
+impl Document {
+    pub fn is_dirty(&self) -> bool {
+        self.revision != self.saved_revision
+    }
+
+    /// A revision no earlier version of the map has had.
+    pub fn next_revision(&mut self) -> u64 {
+        self.last_revision = self.last_revision.max(self.revision) + 1;
+        self.last_revision
+    }
+
+    fn reset(&mut self, path: Option<PathBuf>) {
+        *self = Document {
+            path,
+            ..Default::default()
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileAction {
+    New,
+    NewFromTemplate(Template),
+    Load(PathBuf),
+    Save(PathBuf),
+    /// Writes a copy in another format, without changing which file is being edited.
+    Export(PathBuf),
+    /// Moves the current map's file, and its balance file and script if it has them.
+    Rename(PathBuf),
+    Recover,
+}
+
+/// Sent by the UI. Anything that would lose unsaved work or overwrite another file is held in
+/// `PendingConfirm` until the user confirms it.
+#[derive(Debug, Clone)]
+pub struct FileRequest {
+    pub action: FileAction,
+    pub confirmed: bool,
+}
+
+impl FileRequest {
+    pub fn new(action: FileAction) -> Self {
+        Self {
+            action,
+            confirmed: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PendingConfirm(Option<(FileAction, String)>);
+
+/// The result of the last file operation, shown in the editor window.
+#[derive(Debug, Default)]
+pub struct FileStatus(pub Option<Result<String, String>>);
+
+/// Whether `RECOVERY_PATH` existed when the editor started.
+#[derive(Debug, Default)]
+pub struct RecoveryAvailable(pub bool);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Recovery {
+    path: Option<PathBuf>,
+    map: Map,
+}
+
+/// In the format for the file's extension, so text maps stay text.
+pub fn save_map(path: &Path, map: &Map) -> Result<(), String> {
+    write_atomic(path, &map.encode(path)?)
+}
+
+pub fn save_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let serialized = serde_json::to_vec_pretty(value)
+        .map_err(|e| format!("Could not serialize {:?}: {}", path, e))?;
+    write_atomic(path, &serialized)
+}
+
+/// Writes to a temporary file first, so a failed write can't leave a half written map behind.
+fn write_atomic(path: &Path, serialized: &[u8]) -> Result<(), String> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut f = File::create(&tmp_path)
+        .map_err(|e| format!("Could not open {:?} for writing: {}", tmp_path, e))?;
+    f.write_all(serialized)
+        .and_then(|_| f.sync_all())
+        .map_err(|e| format!("Could not write to {:?}: {}", tmp_path, e))?;
+    fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Could not rename {:?} to {:?}: {}", tmp_path, path, e))
+}
+
+pub fn check_recovery(mut recovery: ResMut<RecoveryAvailable>, mut status: ResMut<FileStatus>) {
+    recovery.0 = Path::new(RECOVERY_PATH).exists();
+    if recovery.0 {
+        status.0 = Some(Ok(
+            "Unsaved work from a previous session can be recovered.".into()
+        ));
+    }
+}
+
+/// A map to open when the editor starts, from `--map`.
+pub struct OpenOnStart(pub PathBuf);
+
+pub fn open_on_start(open: Option<Res<OpenOnStart>>, mut requests: EventWriter<FileRequest>) {
+    if let Some(open) = open {
+        requests.send(FileRequest::new(FileAction::Load(open.0.clone())));
+    }
+}
+
+fn needs_confirm(action: &FileAction, document: &Document) -> Option<String> {
+    match action {
+        FileAction::New
+        | FileAction::NewFromTemplate(_)
+        | FileAction::Load(_)
+        | FileAction::Recover
+            if document.is_dirty() =>
+        {
+            Some("Discard unsaved changes?".into())
+        }
+        FileAction::Save(path) | FileAction::Export(path)
+            if path.exists() && document.path.as_ref() != Some(path) =>
+        {
+            Some(format!("Overwrite {:?}?", path))
+        }
+        _ => None,
+    }
+}
+
+pub fn handle_file_requests(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut map: ResMut<Map>,
+    mut history: ResMut<History>,
+    mut selected: ResMut<SelectedItems>,
+    mut document: ResMut<Document>,
+    mut status: ResMut<FileStatus>,
+    mut pending: ResMut<PendingConfirm>,
+    mut recovery: ResMut<RecoveryAvailable>,
+    mut browser: ResMut<MapBrowser>,
+    mut requests: EventReader<FileRequest>,
+    items: Query<(Entity, &ItemInfo)>,
+) {
+    for request in requests.iter() {
+        if !request.confirmed {
+            if let Some(question) = needs_confirm(&request.action, &document) {
+                pending.0 = Some((request.action.clone(), question));
+                continue;
+            }
+        }
+
+        match &request.action {
+            FileAction::New => {
+                replace_map(
+                    &mut commands,
+                    &mut materials,
+                    &asset_server,
+                    &items,
+                    &mut map,
+                    Map::new(),
+                );
+                document.reset(None);
+                history.clear();
+                selected.clear();
+                status.0 = None;
+            }
+            FileAction::NewFromTemplate(template) => {
+                replace_map(
+                    &mut commands,
+                    &mut materials,
+                    &asset_server,
+                    &items,
+                    &mut map,
+                    template.map(),
+                );
+                document.reset(None);
+                // It hasn't been saved anywhere yet.
+                document.revision = 1;
+                history.clear();
+                selected.clear();
+                status.0 = Some(Ok(format!("New map from the {} template", template.name())));
+            }
+            FileAction::Rename(path) => match rename(&document, path) {
+                Ok(()) => {
+                    document.path = Some(path.clone());
+                    browser.stale = true;
+                    status.0 = Some(Ok(format!("Renamed to {:?}", path)));
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    status.0 = Some(Err(e));
+                }
+            },
+            FileAction::Load(path) => {
+                info!("Loading from {:?}", path);
+                match Map::load(path) {
+                    Ok(new_map) => {
+                        replace_map(
+                            &mut commands,
+                            &mut materials,
+                            &asset_server,
+                            &items,
+                            &mut map,
+                            new_map,
+                        );
+                        history.clear();
+                        selected.clear();
+                        if tiled::is_tiled_path(path) {
+                            // Saving would write over the Tiled map, so it's a new map instead.
+                            document.reset(None);
+                            document.revision = 1;
+                            status.0 = Some(Ok(format!(
+                                "Imported {:?} from Tiled, use Save As to keep it",
+                                path
+                            )));
+                        } else {
+                            document.reset(Some(path.clone()));
+                            status.0 = Some(Ok(format!("Loaded {:?}", path)));
+                        }
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        status.0 = Some(Err(e));
+                    }
+                }
+            }
+            FileAction::Save(path) => {
+                info!("Saving to {:?}", path);
+                match save_map(path, &map) {
+                    Ok(()) => {
+                        let copied = match &document.path {
+                            Some(old) if old != path => copy_companions(old, path),
+                            _ => Ok(()),
+                        };
+                        document.path = Some(path.clone());
+                        document.saved_revision = document.revision;
+                        document.autosaved_revision = document.revision;
+                        let _ = fs::remove_file(RECOVERY_PATH);
+                        recovery.0 = false;
+                        browser.stale = true;
+                        status.0 = Some(match copied {
+                            Ok(()) => Ok(format!("Saved {:?}", path)),
+                            Err(e) => {
+                                warn!("{}", e);
+                                Err(e)
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        status.0 = Some(Err(e));
+                    }
+                }
+            }
+            FileAction::Export(path) => {
+                info!("Exporting to {:?}", path);
+                match save_map(path, &map) {
+                    Ok(()) => {
+                        browser.stale = true;
+                        status.0 = Some(Ok(format!("Exported {:?}", path)));
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        status.0 = Some(Err(e));
+                    }
+                }
+            }
+            FileAction::Recover => {
+                let recovered = File::open(RECOVERY_PATH)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| {
+                        serde_json::from_reader::<_, Recovery>(f).map_err(|e| e.to_string())
+                    });
+                match recovered {
+                    Ok(r) => {
+                        replace_map(
+                            &mut commands,
+                            &mut materials,
+                            &asset_server,
+                            &items,
+                            &mut map,
+                            r.map,
+                        );
+                        document.reset(r.path);
+                        history.clear();
+                        selected.clear();
+                        // Still needs saving.
+                        document.revision = 1;
+                        recovery.0 = false;
+                        status.0 = Some(Ok("Recovered unsaved work".into()));
+                    }
+                    Err(e) => {
+                        let e = format!("Could not recover {:?}: {}", RECOVERY_PATH, e);
+                        warn!("{}", e);
+                        status.0 = Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn rename(document: &Document, path: &Path) -> Result<(), String> {
+    let from = document
+        .path
+        .as_ref()
+        .ok_or_else(|| "Save the map before renaming it.".to_string())?;
+    if path.exists() {
+        return Err(format!("{:?} already exists.", path));
+    }
+
+    let mut moves = vec![(from.clone(), path.to_path_buf())];
+    for (companion_from, companion_to) in companions(from, path) {
+        if companion_from.exists() {
+            moves.push((companion_from, companion_to));
+        }
+    }
+    move_all(&moves)
+}
+
+/// The files that belong to a level besides its map, at `from` and where they'd be for `to`.
+fn companions(from: &Path, to: &Path) -> Vec<(PathBuf, PathBuf)> {
+    vec![
+        (Balance::path_for_map(from), Balance::path_for_map(to)),
+        (scripting::path_for_map(from), scripting::path_for_map(to)),
+    ]
+}
+
+/// Saving as a new file copies the balance file and script along, so the copy plays the same.
+/// Files already at the new path are kept. Maps from templates start without either.
+fn copy_companions(from: &Path, to: &Path) -> Result<(), String> {
+    for (companion_from, companion_to) in companions(from, to) {
+        if companion_from.exists() && !companion_to.exists() {
+            fs::copy(&companion_from, &companion_to)
+                .map_err(|e| format!("Could not copy {:?}: {}", companion_from, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves every file or none of them, so a failure can't leave a map apart from its other files.
+fn move_all(moves: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    for (idx, (from, to)) in moves.iter().enumerate() {
+        if let Err(e) = fs::rename(from, to) {
+            for (done_from, done_to) in moves[..idx].iter().rev() {
+                if let Err(e) = fs::rename(done_to, done_from) {
+                    warn!("Could not move {:?} back: {}", done_to, e);
+                }
+            }
+            return Err(format!("Could not rename {:?}: {}", from, e));
+        }
+    }
+    Ok(())
+}
+
+fn replace_map(
+    commands: &mut Commands,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    asset_server: &Res<AssetServer>,
+    items: &Query<(Entity, &ItemInfo)>,
+    map: &mut Map,
+    new_map: Map,
+) {
+    clear_map(commands, items);
+    for item in &new_map.items {
+        add_item(commands, materials, asset_server, item);
+    }
+    *map = new_map;
+}
+
+pub fn confirm_ui(
+    egui_context: ResMut<EguiContext>,
+    mut pending: ResMut<PendingConfirm>,
+    mut requests: EventWriter<FileRequest>,
+) {
+    let question = match &pending.0 {
+        Some((_, question)) => question.clone(),
+        None => return,
+    };
+
+    let mut answer = None;
+    egui::Window::new("Are you sure?")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx(), |ui| {
+            ui.label(question);
+            ui.horizontal(|ui| {
+                if ui.button("Yes").clicked() {
+                    answer = Some(true);
+                }
+                if ui.button("No").clicked() {
+                    answer = Some(false);
+                }
+            });
+        });
+
+    match answer {
+        Some(true) => {
+            let (action, _) = pending.0.take().unwrap();
+            requests.send(FileRequest {
+                action,
+                confirmed: true,
+            });
+        }
+        Some(false) => pending.0 = None,
+        None => {}
+    }
+}
+
+pub fn autosave(
+    time: Res<Time>,
+    map: Res<Map>,
+    mut document: ResMut<Document>,
+    mut since_last: Local<f32>,
+) {
+    *since_last += time.delta_seconds();
+    if *since_last < AUTOSAVE_SECONDS {
+        return;
+    }
+    *since_last = 0.0;
+
+    if !document.is_dirty() || document.autosaved_revision == document.revision {
+        return;
+    }
+
+    let recovery = Recovery {
+        path: document.path.clone(),
+        map: map.clone(),
+    };
+    match save_json_atomic(Path::new(RECOVERY_PATH), &recovery) {
+        Ok(()) => {
+            debug!("Autosaved to {:?}", RECOVERY_PATH);
+            document.autosaved_revision = document.revision;
+        }
+        Err(e) => warn!("Autosave failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_all_puts_files_back_when_one_fails() {
+        let dir = std::env::temp_dir().join("please-dont-escape-move-all");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let map = dir.join("a.json");
+        fs::write(&map, "{}").unwrap();
+
+        let moves = vec![
+            (map.clone(), dir.join("b.json")),
+            (dir.join("missing.balance.json"), dir.join("b.balance.json")),
+        ];
+        assert!(move_all(&moves).is_err());
+        assert!(map.exists());
+        assert!(!dir.join("b.json").exists());
+    }
+}
@@ -0,0 +1,375 @@
+use super::files::Document;
+use crate::map::{ItemInfo, Map};
+use bevy::prelude::*;
+
+/// A change to the map that can be undone.
+#[derive(Debug, Clone)]
+pub enum EditCommand {
+    Add(Vec<ItemInfo>),
+    Remove(Vec<ItemInfo>),
+    /// Used for moves, rotations and any other edit of existing items.
+    Replace {
+        before: Vec<ItemInfo>,
+        after: Vec<ItemInfo>,
+    },
+}
+
+impl EditCommand {
+    pub fn inverse(&self) -> Self {
+        match self {
+            EditCommand::Add(items) => EditCommand::Remove(items.clone()),
+            EditCommand::Remove(items) => EditCommand::Add(items.clone()),
+            EditCommand::Replace { before, after } => EditCommand::Replace {
+                before: after.clone(),
+                after: before.clone(),
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            EditCommand::Add(items) | EditCommand::Remove(items) => items.is_empty(),
+            EditCommand::Replace { before, after } => before.is_empty() && after.is_empty(),
+        }
+    }
+}
+
+/// Every change to the map in the editor is sent as one of these, so it can be recorded.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Do(EditCommand),
+    /// Like `Do`, but a `Replace` that continues from the previous `Replace` is merged into it, so
+    /// dragging a value in the inspector is a single undo step.
+    DoMerged(EditCommand),
+    Undo,
+    Redo,
+}
+
+/// A command in the history, with the document revisions from before and after it.
+#[derive(Debug, Clone)]
+struct Entry {
+    command: EditCommand,
+    before: u64,
+    after: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    /// The last command came from `Edit::DoMerged`.
+    mergeable: bool,
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.mergeable = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+pub fn undo_redo_keys(keys: Res<Input<KeyCode>>, mut edits: EventWriter<Edit>) {
+    let ctrl = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+    if !ctrl {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        edits.send(Edit::Redo);
+    } else if keys.just_pressed(KeyCode::Z) {
+        edits.send(Edit::Undo);
+    }
+}
+
+pub fn apply_edits(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut map: ResMut<Map>,
+    mut history: ResMut<History>,
+    mut document: ResMut<Document>,
+    mut edits: EventReader<Edit>,
+    items: Query<(Entity, &ItemInfo)>,
+) {
+    // Entities despawned by an earlier edit this frame are still in the query.
+    let mut despawned: Vec<Entity> = vec![];
+
+    for edit in edits.iter() {
+        let mergeable = history.mergeable;
+        history.mergeable = false;
+        let command = match edit {
+            Edit::Do(command) => {
+                if command.is_empty() {
+                    continue;
+                }
+                let entry = Entry {
+                    command: command.clone(),
+                    before: document.revision,
+                    after: document.next_revision(),
+                };
+                document.revision = entry.after;
+                history.undo.push(entry);
+                history.redo.clear();
+                command.clone()
+            }
+            Edit::DoMerged(command) => {
+                if command.is_empty() {
+                    continue;
+                }
+                history.mergeable = true;
+                let revision = document.next_revision();
+                match (history.undo.last_mut(), command) {
+                    (
+                        Some(Entry {
+                            command: EditCommand::Replace { after, .. },
+                            after: after_revision,
+                            ..
+                        }),
+                        EditCommand::Replace {
+                            before: new_before,
+                            after: new_after,
+                        },
+                    ) if mergeable && after == new_before => {
+                        *after = new_after.clone();
+                        *after_revision = revision;
+                    }
+                    _ => history.undo.push(Entry {
+                        command: command.clone(),
+                        before: document.revision,
+                        after: revision,
+                    }),
+                }
+                document.revision = revision;
+                history.redo.clear();
+                command.clone()
+            }
+            Edit::Undo => match history.undo.pop() {
+                Some(entry) => {
+                    let inverse = entry.command.inverse();
+                    document.revision = entry.before;
+                    history.redo.push(entry);
+                    inverse
+                }
+                None => continue,
+            },
+            Edit::Redo => match history.redo.pop() {
+                Some(entry) => {
+                    let command = entry.command.clone();
+                    document.revision = entry.after;
+                    history.undo.push(entry);
+                    command
+                }
+                None => continue,
+            },
+        };
+
+        apply(
+            &mut commands,
+            &mut materials,
+            &asset_server,
+            &mut map,
+            &items,
+            &mut despawned,
+            &command,
+        );
+    }
+}
+
+fn apply(
+    commands: &mut Commands,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    asset_server: &Res<AssetServer>,
+    map: &mut Map,
+    items: &Query<(Entity, &ItemInfo)>,
+    despawned: &mut Vec<Entity>,
+    command: &EditCommand,
+) {
+    let (remove, add) = match command {
+        EditCommand::Add(items) => (&[][..], &items[..]),
+        EditCommand::Remove(items) => (&items[..], &[][..]),
+        EditCommand::Replace { before, after } => (&before[..], &after[..]),
+    };
+
+    for item_info in remove {
+        // Items are identified by value, so only remove one copy of any duplicates.
+        if let Some(idx) = map.items.iter().position(|i| i == item_info) {
+            map.items.remove(idx);
+        }
+        let found = items
+            .iter()
+            .find(|(e, i)| *i == item_info && !despawned.contains(e));
+        if let Some((entity, _)) = found {
+            commands.entity(entity).despawn();
+            despawned.push(entity);
+        }
+    }
+
+    for item_info in add {
+        super::add_item(commands, materials, asset_server, item_info);
+        map.items.push(item_info.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Item;
+    use crate::position::{FlexPosition, GridPosition};
+    use bevy::app::Events;
+    use bevy::asset::AssetPlugin;
+    use bevy::core::CorePlugin;
+
+    fn wall(x: i32, y: i32) -> ItemInfo {
+        ItemInfo::new(Item::Wall, FlexPosition::Grid(GridPosition::new(x, y)), 0.0)
+    }
+
+    /// Just enough of the editor to run `apply_edits`.
+    fn editor() -> App {
+        let mut app = App::build();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<ColorMaterial>()
+            .add_event::<Edit>()
+            .insert_resource(Map::new())
+            .init_resource::<History>()
+            .init_resource::<Document>()
+            .add_system(apply_edits.system());
+        app.app
+    }
+
+    fn send(app: &mut App, edit: Edit) {
+        app.world
+            .get_resource_mut::<Events<Edit>>()
+            .unwrap()
+            .send(edit);
+        app.update();
+    }
+
+    /// The map's items, and the items the editor has entities for, which should always match.
+    fn items(app: &mut App) -> Vec<ItemInfo> {
+        let map = app.world.get_resource::<Map>().unwrap().items.clone();
+        let mut spawned: Vec<ItemInfo> = app
+            .world
+            .query::<&ItemInfo>()
+            .iter(&app.world)
+            .cloned()
+            .collect();
+        let key = |i: &ItemInfo| (i.position.nearest_cell_grid_pos().0.x, i.item.path());
+        let mut sorted = map.clone();
+        sorted.sort_by_key(key);
+        spawned.sort_by_key(key);
+        assert_eq!(sorted, spawned);
+        map
+    }
+
+    fn dirty(app: &App) -> bool {
+        app.world.get_resource::<Document>().unwrap().is_dirty()
+    }
+
+    fn moved(from: i32, to: i32) -> EditCommand {
+        EditCommand::Replace {
+            before: vec![wall(from, 0)],
+            after: vec![wall(to, 0)],
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut app = editor();
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(0, 0)])));
+        send(&mut app, Edit::Do(moved(0, 1)));
+        assert_eq!(items(&mut app), vec![wall(1, 0)]);
+
+        send(&mut app, Edit::Undo);
+        assert_eq!(items(&mut app), vec![wall(0, 0)]);
+        send(&mut app, Edit::Undo);
+        assert!(items(&mut app).is_empty());
+        // Nothing left to undo.
+        send(&mut app, Edit::Undo);
+        assert!(items(&mut app).is_empty());
+
+        send(&mut app, Edit::Redo);
+        send(&mut app, Edit::Redo);
+        assert_eq!(items(&mut app), vec![wall(1, 0)]);
+        assert!(!app.world.get_resource::<History>().unwrap().can_redo());
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut app = editor();
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(0, 0)])));
+        send(&mut app, Edit::Undo);
+        assert!(app.world.get_resource::<History>().unwrap().can_redo());
+
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(5, 0)])));
+        assert!(!app.world.get_resource::<History>().unwrap().can_redo());
+        send(&mut app, Edit::Redo);
+        assert_eq!(items(&mut app), vec![wall(5, 0)]);
+    }
+
+    #[test]
+    fn merged_edits_undo_together() {
+        let mut app = editor();
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(0, 0)])));
+        send(&mut app, Edit::DoMerged(moved(0, 1)));
+        send(&mut app, Edit::DoMerged(moved(1, 2)));
+        assert_eq!(items(&mut app), vec![wall(2, 0)]);
+
+        send(&mut app, Edit::Undo);
+        assert_eq!(items(&mut app), vec![wall(0, 0)]);
+        send(&mut app, Edit::Redo);
+        assert_eq!(items(&mut app), vec![wall(2, 0)]);
+
+        // A plain `Do` in between starts a new step.
+        send(&mut app, Edit::Do(moved(2, 3)));
+        send(&mut app, Edit::DoMerged(moved(3, 4)));
+        send(&mut app, Edit::Undo);
+        assert_eq!(items(&mut app), vec![wall(3, 0)]);
+    }
+
+    #[test]
+    fn only_one_of_identical_items_is_removed() {
+        let mut app = editor();
+        send(
+            &mut app,
+            Edit::Do(EditCommand::Add(vec![wall(0, 0), wall(0, 0)])),
+        );
+        send(&mut app, Edit::Do(EditCommand::Remove(vec![wall(0, 0)])));
+        assert_eq!(items(&mut app), vec![wall(0, 0)]);
+
+        send(&mut app, Edit::Undo);
+        assert_eq!(items(&mut app), vec![wall(0, 0), wall(0, 0)]);
+        // Both copies in one command are removed, one each.
+        send(&mut app, Edit::Undo);
+        assert!(items(&mut app).is_empty());
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_map_is_not_dirty() {
+        let mut app = editor();
+        assert!(!dirty(&app));
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(0, 0)])));
+        assert!(dirty(&app));
+        send(&mut app, Edit::Undo);
+        assert!(!dirty(&app));
+        send(&mut app, Edit::Redo);
+        assert!(dirty(&app));
+
+        // An edit after undoing is a new version, even though it's one edit from the saved map.
+        send(&mut app, Edit::Undo);
+        send(&mut app, Edit::Do(EditCommand::Add(vec![wall(1, 0)])));
+        assert!(dirty(&app));
+        send(&mut app, Edit::Undo);
+        assert!(!dirty(&app));
+    }
+}
@@ -0,0 +1,107 @@
+use super::history::{Edit, EditCommand};
+use super::selection::SelectedItems;
+use crate::map::{Item, ItemInfo};
+use crate::position::{FlexPosition, GridPosition, Position};
+use bevy::prelude::*;
+use bevy_egui::egui::Ui;
+use bevy_egui::{egui, EguiContext};
+use nalgebra::Vector2;
+
+/// Edits a copy of the selected item, then replaces the original with it when anything changed.
+pub fn inspector_ui(
+    egui_context: ResMut<EguiContext>,
+    mut selected: ResMut<SelectedItems>,
+    mut edits: EventWriter<Edit>,
+) {
+    if selected.0.len() != 1 {
+        return;
+    }
+
+    let before = selected.0[0].clone();
+    let mut after = before.clone();
+
+    egui::Window::new("Inspector")
+        .default_width(200.0)
+        .show(egui_context.ctx(), |ui| {
+            ui.heading(format!("{:?}", after.item));
+            rotation(ui, &mut after);
+            position(ui, &mut after);
+            properties(ui, &mut after);
+        });
+
+    if after != before {
+        edits.send(Edit::DoMerged(EditCommand::Replace {
+            before: vec![before],
+            after: vec![after.clone()],
+        }));
+        selected.0[0] = after;
+    }
+}
+
+fn rotation(ui: &mut Ui, item_info: &mut ItemInfo) {
+    ui.horizontal(|ui| {
+        ui.label("Rotation:");
+        for rotation in [0.0, 90.0, 180.0, 270.0] {
+            if ui
+                .selectable_label(item_info.rotation == rotation, format!("{}", rotation))
+                .clicked()
+            {
+                item_info.rotation = rotation;
+            }
+        }
+        ui.add(egui::DragValue::new(&mut item_info.rotation).speed(1.0));
+    });
+    item_info.rotation = item_info.rotation.rem_euclid(360.0);
+}
+
+fn position(ui: &mut Ui, item_info: &mut ItemInfo) {
+    let mut snapped = matches!(item_info.position, FlexPosition::Grid(_));
+    if ui.checkbox(&mut snapped, "Snap to grid").changed() {
+        item_info.position = if snapped {
+            FlexPosition::Grid(item_info.position.nearest_cell_grid_pos())
+        } else {
+            FlexPosition::Position(item_info.position.into())
+        };
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Position:");
+        match &mut item_info.position {
+            FlexPosition::Grid(GridPosition(v)) => {
+                ui.add(egui::DragValue::new(&mut v.x).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut v.y).prefix("y: "));
+            }
+            FlexPosition::Position(Position(v)) => {
+                let v: &mut Vector2<f64> = v;
+                ui.add(egui::DragValue::new(&mut v.x).speed(0.05).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut v.y).speed(0.05).prefix("y: "));
+            }
+        }
+    });
+}
+
+fn properties(ui: &mut Ui, item_info: &mut ItemInfo) {
+    match &mut item_info.item {
+        Item::Background(path) => {
+            ui.horizontal(|ui| {
+                ui.label("Image path:");
+                ui.text_edit_singleline(path);
+            });
+        }
+        Item::Door => {
+            ui.checkbox(&mut item_info.properties.door_open, "Starts open");
+            circuit(ui, &mut item_info.properties.circuit);
+        }
+        Item::Wire => {
+            circuit(ui, &mut item_info.properties.circuit);
+        }
+        _ => {}
+    }
+}
+
+fn circuit(ui: &mut Ui, circuit: &mut u32) {
+    ui.horizontal(|ui| {
+        ui.label("Circuit:");
+        ui.add(egui::DragValue::new(circuit));
+    });
+}
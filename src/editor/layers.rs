@@ -0,0 +1,109 @@
+use super::Mode;
+use crate::map::{Item, ItemInfo, Layer};
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_egui::{egui, EguiContext};
+
+/// Which layers are shown and locked, and the one the select modes work on.
+#[derive(Debug)]
+pub struct EditorLayers {
+    pub active: Layer,
+    hidden: HashSet<Layer>,
+    locked: HashSet<Layer>,
+}
+
+impl Default for EditorLayers {
+    fn default() -> Self {
+        Self {
+            active: Layer::Structure,
+            hidden: HashSet::default(),
+            locked: HashSet::default(),
+        }
+    }
+}
+
+impl EditorLayers {
+    pub fn is_visible(&self, layer: Layer) -> bool {
+        !self.hidden.contains(&layer)
+    }
+
+    pub fn is_locked(&self, layer: Layer) -> bool {
+        self.locked.contains(&layer)
+    }
+
+    /// Items can only be added to or changed in layers that are shown and unlocked.
+    pub fn is_editable(&self, layer: Layer) -> bool {
+        self.is_visible(layer) && !self.is_locked(layer)
+    }
+
+    /// Only items in the active layer can be selected, so overlapping items on other layers
+    /// don't get in the way.
+    pub fn is_selectable(&self, item: &Item) -> bool {
+        item.layer() == self.active && self.is_editable(self.active)
+    }
+
+    fn set(set: &mut HashSet<Layer>, layer: Layer, on: bool) {
+        if on {
+            set.insert(layer);
+        } else {
+            set.remove(&layer);
+        }
+    }
+}
+
+pub fn layers_ui(egui_context: ResMut<EguiContext>, mut layers: ResMut<EditorLayers>) {
+    egui::Window::new("Layers")
+        .default_width(200.0)
+        .default_pos([10.0, 450.0])
+        .show(egui_context.ctx(), |ui| {
+            // Top layer first, the same as most drawing programs.
+            for layer in Layer::all().iter().rev() {
+                let layer = *layer;
+                ui.horizontal(|ui| {
+                    let mut visible = layers.is_visible(layer);
+                    let mut locked = layers.is_locked(layer);
+                    if ui.checkbox(&mut visible, "Show").changed() {
+                        EditorLayers::set(&mut layers.hidden, layer, !visible);
+                    }
+                    if ui.checkbox(&mut locked, "Lock").changed() {
+                        EditorLayers::set(&mut layers.locked, layer, locked);
+                    }
+                    if ui
+                        .selectable_label(layers.active == layer, layer.name())
+                        .clicked()
+                    {
+                        layers.active = layer;
+                    }
+                });
+            }
+        });
+}
+
+/// Picking an item to add makes its layer the active one.
+pub fn follow_item_layer(mode: Res<Mode>, item: Res<Item>, mut layers: ResMut<EditorLayers>) {
+    if *mode != Mode::Add || !item.is_changed() {
+        return;
+    }
+    let layer = item.layer();
+    if layers.active != layer {
+        layers.active = layer;
+    }
+}
+
+/// Hides the sprites of hidden layers. Only runs when the layers change or an edit spawns or
+/// changes items, which then checks every item.
+pub fn apply_layer_visibility(
+    layers: Res<EditorLayers>,
+    changed: Query<(), Changed<ItemInfo>>,
+    mut items: Query<(&ItemInfo, &mut Visible)>,
+) {
+    if !layers.is_changed() && changed.iter().next().is_none() {
+        return;
+    }
+    for (item_info, mut visible) in items.iter_mut() {
+        let shown = layers.is_visible(item_info.item.layer());
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use crate::debug;
+use crate::debug::{DebugOverlay, OverlayMaterials};
+use crate::game::{Level, Playtest, GRID_SIZE};
+use crate::map::{angle_to_quat, Item, ItemInfo, Map};
+use crate::position::{FlexPosition, GridPosition, Position};
+use crate::AppState;
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy_egui::egui::{FontDefinitions, Ui};
+use bevy_egui::{egui, EguiContext};
+
+mod browser;
+mod camera;
+mod files;
+mod history;
+mod inspector;
+mod layers;
+mod prefabs;
+mod selection;
+mod tools;
+
+use browser::MapBrowser;
+pub use files::OpenOnStart;
+use files::{Document, FileAction, FileRequest, FileStatus, PendingConfirm, RecoveryAvailable};
+use history::{Edit, EditCommand, History};
+use layers::EditorLayers;
+use prefabs::PrefabPalette;
+use selection::{Clipboard, SelectedItems};
+use tools::{add_items_command, cursor_cell, Tool, ToolDrag};
+
+pub struct Editor;
+
+impl Plugin for Editor {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            //
+            .init_resource::<camera::Drag>()
+            .init_resource::<camera::GridMaterial>()
+            .insert_resource(Map::new())
+            .insert_resource(UiFilename("level1".into()))
+            .insert_resource(Mode::Add)
+            .insert_resource(Item::Wall)
+            .insert_resource(ItemRotation(0.0))
+            .init_resource::<SelectedItems>()
+            .init_resource::<Clipboard>()
+            .init_resource::<DebugOverlay>()
+            .init_resource::<OverlayMaterials>()
+            .init_resource::<History>()
+            .init_resource::<EditorSettings>()
+            .init_resource::<EditorLayers>()
+            .insert_resource(Tool::Single)
+            .init_resource::<ToolDrag>()
+            .add_event::<Edit>()
+            .init_resource::<Document>()
+            .init_resource::<FileStatus>()
+            .init_resource::<PendingConfirm>()
+            .init_resource::<RecoveryAvailable>()
+            .add_event::<FileRequest>()
+            .init_resource::<MapBrowser>()
+            .init_resource::<PrefabPalette>()
+            .add_startup_system(files::check_recovery.system())
+            .add_startup_system(files::open_on_start.system())
+            //
+            .add_system_set(
+                SystemSet::on_enter(AppState::Editor)
+                    .with_system(setup.system())
+                    .with_system(spawn_map.system())
+                    .with_system(end_playtest.system()),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Editor).with_system(cleanup.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Editor)
+                    .with_system(ui.system())
+                    .with_system(selection_follows_mouse.system())
+                    .with_system(click_add.system())
+                    .with_system(tools::drag_tool.system())
+                    .with_system(selection::click_select.system())
+                    .with_system(selection::selection_keys.system())
+                    .with_system(selection::prune_selection.system())
+                    .with_system(selection::highlight_selection.system())
+                    .with_system(inspector::inspector_ui.system())
+                    .with_system(layers::layers_ui.system())
+                    .with_system(layers::follow_item_layer.system())
+                    .with_system(layers::apply_layer_visibility.system())
+                    .with_system(camera::drag_diff.system())
+                    .with_system(camera::drag.system())
+                    .with_system(camera::zoom_camera.system())
+                    .with_system(camera::pan_keys.system())
+                    .with_system(camera::toggle_grid.system())
+                    .with_system(camera::draw_grid.system())
+                    .with_system(rotate_key.system())
+                    .with_system(history::undo_redo_keys.system())
+                    .with_system(history::apply_edits.system())
+                    .with_system(files::handle_file_requests.system())
+                    .with_system(files::confirm_ui.system())
+                    .with_system(files::autosave.system())
+                    .with_system(browser::refresh_browser.system())
+                    .with_system(browser::browser_ui.system())
+                    .with_system(prefabs::refresh_palette.system())
+                    .with_system(prefabs::prefabs_ui.system())
+                    .with_system(prefabs::mirror_key.system())
+                    .with_system(prefabs::click_place.system())
+                    .with_system(prefabs::preview_prefab.system())
+                    .with_system(debug::toggle_overlay.system())
+                    .with_system(debug::draw_editor_overlay.system()),
+            );
+    }
+}
+
+struct Selection;
+
+/// Editor options that don't change the map itself.
+#[derive(Debug)]
+pub struct EditorSettings {
+    /// Pick wall pieces and rotations from their neighbours when placing walls.
+    pub autotile: bool,
+    /// Lines along the cell edges, toggled with G.
+    pub grid: bool,
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            autotile: true,
+            grid: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemRotation(f32);
+
+fn setup(
+    mut commands: Commands,
+    egui_context: ResMut<EguiContext>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let fonts = FontDefinitions::default();
+    egui_context.ctx().set_fonts(fonts);
+
+    let style: egui::Style = egui::Style::default();
+    egui_context.ctx().set_style(style);
+
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.transform.scale = Vec3::new(camera::DEFAULT_ZOOM, camera::DEFAULT_ZOOM, 1.0);
+    commands.spawn().insert_bundle(camera);
+
+    let selection = materials.add(asset_server.load("cells/selection.png").into());
+    let grid_pos = GridPosition::zero();
+    let transform = Position::from(grid_pos).to_transform();
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: selection,
+            transform,
+            ..Default::default()
+        })
+        .insert(Selection);
+}
+
+/// Items are despawned when leaving the editor, e.g. to playtest, so bring them back.
+fn spawn_map(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    map: Res<Map>,
+) {
+    for item in &map.items {
+        add_item(&mut commands, &mut materials, &asset_server, item);
+    }
+}
+
+/// Back from playtesting, if that's how the editor was entered, so Escape in the game no longer
+/// comes back here.
+fn end_playtest(mut playtest: ResMut<Playtest>) {
+    playtest.0 = false;
+}
+
+fn cleanup(
+    mut commands: Commands,
+    mut selected: ResMut<SelectedItems>,
+    entities: Query<Entity>,
+) {
+    debug!("Cleanup.");
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    selected.clear();
+}
+
+fn clear_map(commands: &mut Commands, items: &Query<(Entity, &ItemInfo)>) {
+    for (ent, _) in items.iter() {
+        commands.entity(ent).despawn();
+    }
+}
+
+fn ui(
+    egui_context: ResMut<EguiContext>,
+    ui_filename: Res<UiFilename>,
+    (mut mode, mut tool, mut item, mut item_rotation): (
+        ResMut<Mode>,
+        ResMut<Tool>,
+        ResMut<Item>,
+        ResMut<ItemRotation>,
+    ),
+    map: Res<Map>,
+    mut selected: ResMut<SelectedItems>,
+    (mut overlay, mut settings): (ResMut<DebugOverlay>, ResMut<EditorSettings>),
+    history: Res<History>,
+    mut edits: EventWriter<Edit>,
+    (mut state, mut level, mut playtest): (ResMut<State<AppState>>, ResMut<Level>, ResMut<Playtest>),
+    (document, status, recovery): (Res<Document>, Res<FileStatus>, Res<RecoveryAvailable>),
+    mut file_requests: EventWriter<FileRequest>,
+    selection: Query<&Transform, With<Selection>>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    egui::Window::new("Editor")
+        .default_width(200.0)
+        .show(egui_context.ctx(), |ui| {
+            if ui.button("Play").on_hover_text("Playtest the current map").clicked() {
+                *level = Level {
+                    path: Some(
+                        document
+                            .path
+                            .clone()
+                            .unwrap_or_else(|| browser::map_path(&ui_filename.0)),
+                    ),
+                    map: Some(map.clone()),
+                };
+                playtest.0 = true;
+                state.set(AppState::InGame).unwrap();
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("New").clicked() {
+                    file_requests.send(FileRequest::new(FileAction::New));
+                };
+                if ui.button("Save").clicked() {
+                    let path = document
+                        .path
+                        .clone()
+                        .unwrap_or_else(|| browser::map_path(&ui_filename.0));
+                    file_requests.send(FileRequest::new(FileAction::Save(path)));
+                }
+                if ui
+                    .button("Export binary")
+                    .on_hover_text("Save a copy as a .bin file, which loads faster")
+                    .clicked()
+                {
+                    let path = document
+                        .path
+                        .clone()
+                        .unwrap_or_else(|| browser::map_path(&ui_filename.0))
+                        .with_extension("bin");
+                    file_requests.send(FileRequest::new(FileAction::Export(path)));
+                }
+                if recovery.0 && ui.button("Recover").clicked() {
+                    file_requests.send(FileRequest::new(FileAction::Recover));
+                }
+            });
+
+            match &document.path {
+                Some(path) => ui.label(format!("Editing {:?}", path)),
+                None => ui.label("New map, use Save As in Maps to name it"),
+            };
+            if document.is_dirty() {
+                ui.colored_label(egui::Color32::YELLOW, "● Unsaved changes");
+            }
+            match &status.0 {
+                Some(Ok(message)) => {
+                    ui.label(message);
+                }
+                Some(Err(message)) => {
+                    ui.colored_label(egui::Color32::RED, message);
+                }
+                None => {}
+            }
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add(egui::Button::new("Undo").enabled(history.can_undo()))
+                    .on_hover_text("Ctrl+Z")
+                    .clicked()
+                {
+                    edits.send(Edit::Undo);
+                }
+                if ui
+                    .add(egui::Button::new("Redo").enabled(history.can_redo()))
+                    .on_hover_text("Ctrl+Y")
+                    .clicked()
+                {
+                    edits.send(Edit::Redo);
+                }
+            });
+
+            ui.checkbox(&mut overlay.0, "Pathfinding overlay (F3)");
+            ui.checkbox(&mut settings.autotile, "Auto-tile walls");
+            ui.checkbox(&mut settings.grid, "Grid (G)");
+            let cell = cursor_cell(selection.single().unwrap());
+            ui.label(format!(
+                "Cursor: ({}, {}), zoom {:.1}x",
+                cell.0.x,
+                cell.0.y,
+                camera::DEFAULT_ZOOM / cameras.single().unwrap().scale.x
+            ));
+
+            ui.separator();
+
+            ui.heading("Mode");
+            ui.horizontal_wrapped(|ui| {
+                select_mode(ui, "Add", &mut mode, Mode::Add);
+                select_mode(ui, "Select", &mut mode, Mode::Select);
+                select_mode(ui, "Select Specific", &mut mode, Mode::SelectSpecific);
+                select_mode(ui, "Prefab", &mut mode, Mode::Prefab);
+            });
+
+            if *mode == Mode::Add {
+                ui.heading("Tool");
+                ui.horizontal_wrapped(|ui| {
+                    select_tool(ui, "Single", &mut tool, Tool::Single);
+                    select_tool(ui, "Line", &mut tool, Tool::Line);
+                    select_tool(ui, "Rectangle", &mut tool, Tool::Rectangle);
+                    select_tool(ui, "Outline", &mut tool, Tool::RectangleOutline);
+                    select_tool(ui, "Room", &mut tool, Tool::Room);
+                });
+            }
+
+            ui.heading("Item");
+            ui.horizontal_wrapped(|ui| {
+                select_item(ui, "General Tile", &mut item, Item::GeneralTile);
+                select_item(ui, "Cell Tile", &mut item, Item::CellTile);
+                select_item(ui, "Wall", &mut item, Item::Wall);
+                select_item(ui, "Wall Corner", &mut item, Item::WallCorner);
+                select_item(ui, "Warden Spawn", &mut item, Item::Warden);
+                select_item(ui, "Prisoner Spawn", &mut item, Item::Prisoner);
+                select_item(ui, "Security Door", &mut item, Item::Door);
+                select_item(ui, "Exit", &mut item, Item::Exit);
+                select_item(ui, "Wire", &mut item, Item::Wire);
+                select_item(
+                    ui,
+                    "Background Image",
+                    &mut item,
+                    Item::Background("menus/logo.png".into()),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Rotation:");
+                for rotation in [0.0, 90.0, 180.0, 270.0] {
+                    if ui
+                        .selectable_label(item_rotation.0 == rotation, format!("{}", rotation))
+                        .clicked()
+                    {
+                        item_rotation.0 = rotation;
+                    }
+                }
+            });
+
+            if let Item::Background(b) = &mut *item {
+                ui.horizontal(|ui| {
+                    ui.label("Image path:");
+                    ui.text_edit_singleline(b);
+                });
+            }
+
+            ui.separator();
+
+            ui.heading("Selected");
+            match selected.0.len() {
+                0 => {
+                    ui.label("Nothing selected");
+                }
+                1 => {
+                    ui.label(format!("{:?}, see the Inspector", selected.0[0].item));
+                }
+                n => {
+                    ui.label(format!("{} items", n));
+                }
+            }
+            if !selected.0.is_empty() {
+                ui.label("Drag to move, R to rotate, Ctrl+C/X/V to copy, cut and paste.");
+                if ui.button("Delete").clicked() {
+                    edits.send(Edit::Do(EditCommand::Remove(selected.0.clone())));
+                    selected.clear();
+                }
+            }
+        });
+}
+
+// TODO: Work out how to make generic
+fn select_item(ui: &mut Ui, title: &str, item: &mut ResMut<Item>, new_item: Item) {
+    if ui.selectable_label(**item == new_item, title).clicked() {
+        **item = new_item;
+    };
+}
+
+fn select_tool(ui: &mut Ui, title: &str, item: &mut ResMut<Tool>, new_item: Tool) {
+    if ui.selectable_label(**item == new_item, title).clicked() {
+        **item = new_item;
+    };
+}
+
+fn select_mode(ui: &mut Ui, title: &str, item: &mut ResMut<Mode>, new_item: Mode) -> bool {
+    if ui.selectable_label(**item == new_item, title).clicked() {
+        **item = new_item;
+        return true;
+    };
+    false
+}
+
+#[derive(Debug, PartialEq)]
+struct PreviousItem {
+    mode: Mode,
+    item: Item,
+    rotation: ItemRotation,
+}
+
+impl Default for PreviousItem {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Add,
+            item: Item::Warden,
+            rotation: ItemRotation(0.0),
+        }
+    }
+}
+
+fn selection_follows_mouse(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    cameras: Query<&Transform, With<Camera>>,
+    selections: Query<Entity, With<Selection>>,
+    mode: Res<Mode>,
+    item: Res<Item>,
+    item_rotation: Res<ItemRotation>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut previous: Local<PreviousItem>,
+) {
+    let camera_transform = cameras.single().expect("Wrong amount of cameras.");
+    let window = windows.get_primary().unwrap();
+    let world_pos = match camera::cursor_world_pos(window, camera_transform) {
+        Some(p) => p,
+        None => return,
+    };
+    let mut transform = Transform::from_xyz(world_pos.x, world_pos.y, 0.0);
+
+    // Snap!
+    let snapped_pos = (transform.translation / GRID_SIZE).round() * GRID_SIZE;
+    transform.translation = snapped_pos;
+    transform.translation.z = 5.0; // bring selection/add preview to the front
+    transform.rotation = angle_to_quat(item_rotation.0);
+
+    let selection = selections.single().expect("Wrong amount of selections.");
+    let mut ent_cmd = commands.entity(selection);
+
+    let new = PreviousItem {
+        mode: mode.clone(),
+        item: item.clone(),
+        rotation: item_rotation.clone(),
+    };
+    if new != *previous {
+        if *mode == Mode::Add {
+            let material = materials.add(asset_server.load(item.path()).into());
+            ent_cmd.insert(material);
+        } else {
+            let material = materials.add(asset_server.load("cells/selection.png").into());
+            ent_cmd.insert(material);
+        }
+    }
+    ent_cmd.insert(transform);
+    *previous = new;
+}
+
+/// In the select modes, R rotates the selection instead. See `selection::selection_keys`.
+fn rotate_key(
+    keys: Res<Input<KeyCode>>,
+    mode: Res<Mode>,
+    mut item_rotation: ResMut<ItemRotation>,
+) {
+    if *mode != Mode::Add && *mode != Mode::Prefab {
+        return;
+    }
+    if keys.just_pressed(KeyCode::R) {
+        item_rotation.0 += 90.0;
+        if item_rotation.0 == 360.0 {
+            *item_rotation = ItemRotation(0.0);
+        }
+    }
+}
+
+fn click_add(
+    mut edits: EventWriter<Edit>,
+    map: Res<Map>,
+    settings: Res<EditorSettings>,
+    layers: Res<EditorLayers>,
+    button: Res<Input<MouseButton>>,
+    mode: Res<Mode>,
+    tool: Res<Tool>,
+    item: Res<Item>,
+    item_rotation: Res<ItemRotation>,
+    selection: Query<&Transform, With<Selection>>,
+    egui_context: Res<EguiContext>,
+) {
+    if egui_context.ctx().is_pointer_over_area() {
+        return;
+    }
+    if !button.just_pressed(MouseButton::Left) {
+        return;
+    }
+    if *mode != Mode::Add || *tool != Tool::Single {
+        return;
+    }
+    if !layers.is_editable(item.layer()) {
+        return;
+    }
+
+    let transform = selection.single().unwrap();
+    let pos: Position = (transform.translation.truncate() / GRID_SIZE).into();
+    let item_info = ItemInfo::new(
+        item.clone(),
+        FlexPosition::Grid(pos.nearest_cell()),
+        item_rotation.0,
+    );
+
+    edits.send(Edit::Do(add_items_command(&map, &settings, vec![item_info])));
+}
+
+fn add_item(
+    commands: &mut Commands,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    asset_server: &Res<AssetServer>,
+    item_info: &ItemInfo,
+) {
+    let material = materials.add(asset_server.load(item_info.item.path()).into());
+    let pos: Position = item_info.position.into();
+    let mut transform = Transform::from_translation((pos * GRID_SIZE as f64).into());
+    transform.translation.z = item_info.item.layer().z();
+    transform.rotation = item_info.quat();
+    commands
+        .spawn_bundle(SpriteBundle {
+            material,
+            transform,
+            ..Default::default()
+        })
+        .insert(item_info.clone());
+}
+
+/// The name box in the map browser, without `MAPS_DIR` or the extension.
+pub struct UiFilename(pub String);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Add,
+    Select,
+    SelectSpecific,
+    /// Placing the prefab picked in `prefabs::PrefabPalette`.
+    Prefab,
+}
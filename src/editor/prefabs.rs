@@ -0,0 +1,273 @@
+use super::files::{save_json_atomic, FileStatus};
+use super::history::{Edit, EditCommand};
+use super::layers::EditorLayers;
+use super::selection::{centre, SelectedItems};
+use super::tools::{add_items_command, cursor_cell};
+use super::{EditorSettings, ItemRotation, Mode, Selection};
+use crate::map::{ItemInfo, Map};
+use crate::position::{GridPosition, Position};
+use crate::prefab::{Prefab, PrefabLink, PREFABS_DIR};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The prefab files in `PREFABS_DIR`, and how the next placement will be put down. The rotation
+/// is the same `ItemRotation` used for adding items.
+#[derive(Debug)]
+pub struct PrefabPalette {
+    entries: Vec<PathBuf>,
+    selected: Option<(PathBuf, Prefab)>,
+    mirrored: bool,
+    /// Set when the files need listing again.
+    stale: bool,
+    /// For saving a new prefab.
+    name: String,
+}
+
+impl Default for PrefabPalette {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            selected: None,
+            mirrored: false,
+            stale: true,
+            name: "cell".into(),
+        }
+    }
+}
+
+impl PrefabPalette {
+    fn link(&self, map: &Map, cell: GridPosition, rotation: f32) -> Option<(PrefabLink, &Prefab)> {
+        let (path, prefab) = self.selected.as_ref()?;
+        let link = PrefabLink {
+            path: path.to_string_lossy().to_string(),
+            instance: map.next_prefab_instance(),
+            origin: cell,
+            quarter_turns: ((rotation / 90.0).round() as i32).rem_euclid(4) as u8,
+            mirrored: self.mirrored,
+        };
+        Some((link, prefab))
+    }
+}
+
+/// Copies of the prefab's items where `click_place` would put them.
+pub struct PrefabPreview;
+
+pub fn refresh_palette(mut palette: ResMut<PrefabPalette>) {
+    if !palette.stale {
+        return;
+    }
+    palette.stale = false;
+    palette.entries = match fs::read_dir(PREFABS_DIR) {
+        Ok(r) => r
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .collect(),
+        // There aren't any yet.
+        Err(_) => vec![],
+    };
+    palette.entries.sort();
+}
+
+/// Every item inside the bounds of the selection, on any layer, so a prefab of a cell can be
+/// made by selecting its walls.
+fn prefab_items(map: &Map, selected: &[ItemInfo]) -> Vec<ItemInfo> {
+    let bounds = Map::from_items(selected.to_vec()).bounds();
+    let (min, max) = match bounds {
+        Some(b) => b,
+        None => return vec![],
+    };
+    map.items
+        .iter()
+        .filter(|i| {
+            let cell = i.position.nearest_cell_grid_pos();
+            (min.0.x..=max.0.x).contains(&cell.0.x) && (min.0.y..=max.0.y).contains(&cell.0.y)
+        })
+        .cloned()
+        .collect()
+}
+
+pub fn prefabs_ui(
+    egui_context: ResMut<EguiContext>,
+    mut palette: ResMut<PrefabPalette>,
+    mut mode: ResMut<Mode>,
+    map: Res<Map>,
+    selected: Res<SelectedItems>,
+    mut edits: EventWriter<Edit>,
+    mut status: ResMut<FileStatus>,
+) {
+    egui::Window::new("Prefabs")
+        .default_width(200.0)
+        .default_pos([270.0, 600.0])
+        .show(egui_context.ctx(), |ui| {
+            if ui.button("Refresh").clicked() {
+                palette.stale = true;
+            }
+
+            let mut picked = None;
+            egui::ScrollArea::from_max_height(150.0).show(ui, |ui| {
+                for path in &palette.entries {
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let is_selected = palette.selected.as_ref().map(|(p, _)| p) == Some(path);
+                    if ui.selectable_label(is_selected, name).clicked() {
+                        picked = Some(path.clone());
+                    }
+                }
+            });
+            if let Some(path) = picked {
+                match Prefab::load(&path) {
+                    Ok(prefab) => {
+                        palette.selected = Some((path, prefab));
+                        *mode = Mode::Prefab;
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        status.0 = Some(Err(e));
+                    }
+                }
+            }
+
+            ui.checkbox(&mut palette.mirrored, "Mirror (M)");
+            if *mode == Mode::Prefab {
+                ui.label("Click to place, R to rotate.");
+            }
+
+            if ui
+                .button("Update placements")
+                .on_hover_text("Reload every prefab placed in this map from its file")
+                .clicked()
+            {
+                let mut refreshed = map.clone();
+                let errors = refreshed.refresh_prefabs();
+                let linked = |m: &Map| -> Vec<ItemInfo> {
+                    m.items
+                        .iter()
+                        .filter(|i| i.properties.prefab.is_some())
+                        .cloned()
+                        .collect()
+                };
+                edits.send(Edit::Do(EditCommand::Replace {
+                    before: linked(&map),
+                    after: linked(&refreshed),
+                }));
+                status.0 = Some(if errors.is_empty() {
+                    Ok("Updated prefab placements".into())
+                } else {
+                    Err(errors.join("\n"))
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Name:");
+                ui.text_edit_singleline(&mut palette.name);
+            });
+            let save =
+                egui::Button::new("Save selection as prefab").enabled(!selected.0.is_empty());
+            if ui
+                .add(save)
+                .on_hover_text("Everything inside the selection, on every layer")
+                .clicked()
+            {
+                let path = Path::new(PREFABS_DIR).join(format!("{}.json", palette.name));
+                let prefab = Prefab::new(centre(&selected.0), &prefab_items(&map, &selected.0));
+                let result = fs::create_dir_all(PREFABS_DIR)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| save_json_atomic(&path, &prefab));
+                status.0 = Some(match result {
+                    Ok(()) => Ok(format!("Saved prefab {:?}", path)),
+                    Err(e) => Err(e),
+                });
+                palette.stale = true;
+            }
+        });
+}
+
+pub fn mirror_key(keys: Res<Input<KeyCode>>, mode: Res<Mode>, mut palette: ResMut<PrefabPalette>) {
+    if *mode == Mode::Prefab && keys.just_pressed(KeyCode::M) {
+        palette.mirrored = !palette.mirrored;
+    }
+}
+
+pub fn click_place(
+    mut edits: EventWriter<Edit>,
+    (map, settings, layers): (Res<Map>, Res<EditorSettings>, Res<EditorLayers>),
+    palette: Res<PrefabPalette>,
+    mode: Res<Mode>,
+    item_rotation: Res<ItemRotation>,
+    button: Res<Input<MouseButton>>,
+    mut status: ResMut<FileStatus>,
+    selection: Query<&Transform, With<Selection>>,
+    egui_context: Res<EguiContext>,
+) {
+    if egui_context.ctx().is_pointer_over_area() {
+        return;
+    }
+    if *mode != Mode::Prefab || !button.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let cell = cursor_cell(selection.single().unwrap());
+    let (link, prefab) = match palette.link(&map, cell, item_rotation.0) {
+        Some(l) => l,
+        None => return,
+    };
+    let items = prefab.place(&link);
+    // Leaving some out would be undone the next time the placement is refreshed.
+    if !items.iter().all(|i| layers.is_editable(i.item.layer())) {
+        status.0 = Some(Err(
+            "The prefab has items on a hidden or locked layer.".into()
+        ));
+        return;
+    }
+    edits.send(Edit::Do(add_items_command(&map, &settings, items)));
+}
+
+pub fn preview_prefab(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    (map, palette, mode, item_rotation): (
+        Res<Map>,
+        Res<PrefabPalette>,
+        Res<Mode>,
+        Res<ItemRotation>,
+    ),
+    mut last_cell: Local<Option<GridPosition>>,
+    selection: Query<&Transform, With<Selection>>,
+    previews: Query<Entity, With<PrefabPreview>>,
+) {
+    let cell = cursor_cell(selection.single().unwrap());
+    let changed = palette.is_changed() || mode.is_changed() || item_rotation.is_changed();
+    if !changed && *last_cell == Some(cell) {
+        return;
+    }
+    *last_cell = Some(cell);
+
+    for entity in previews.iter() {
+        commands.entity(entity).despawn();
+    }
+    if *mode != Mode::Prefab {
+        return;
+    }
+    let (link, prefab) = match palette.link(&map, cell, item_rotation.0) {
+        Some(l) => l,
+        None => return,
+    };
+    for item_info in prefab.place(&link) {
+        let material = materials.add(asset_server.load(item_info.item.path()).into());
+        let pos: Position = item_info.position.into();
+        let mut transform = pos.to_transform();
+        transform.translation.z = 4.0;
+        transform.rotation = item_info.quat();
+        commands
+            .spawn_bundle(SpriteBundle {
+                material,
+                transform,
+                ..Default::default()
+            })
+            .insert(PrefabPreview);
+    }
+}
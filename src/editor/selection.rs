@@ -0,0 +1,588 @@
+use super::history::{Edit, EditCommand};
+use super::layers::EditorLayers;
+use super::tools::{add_items_command, cursor_cell};
+use super::{EditorSettings, Mode, Selection};
+use crate::map::{Item, ItemInfo, Map};
+use crate::position::{FlexPosition, GridPosition, Position};
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use nalgebra::Vector2;
+
+/// Items are identified by value, the same way they are in `Map.items`.
+#[derive(Debug, Default)]
+pub struct SelectedItems(pub Vec<ItemInfo>);
+
+impl SelectedItems {
+    pub fn contains(&self, item_info: &ItemInfo) -> bool {
+        self.0.contains(item_info)
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Copied items as JSON, in the same format as a map file, relative to where they were copied
+/// from. Kept between map loads so items can be copied from one map to another.
+#[derive(Debug, Default)]
+pub struct Clipboard(pub Option<String>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectDrag {
+    Box(GridPosition),
+    Move(GridPosition),
+}
+
+/// The outline shown over each selected item.
+pub struct SelectionHighlight;
+
+fn shift_pressed(keys: &Input<KeyCode>) -> bool {
+    keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift)
+}
+
+fn ctrl_pressed(keys: &Input<KeyCode>) -> bool {
+    keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl)
+}
+
+/// Which items the select modes pick: only those on the active layer, and in
+/// `Mode::SelectSpecific` only the chosen item.
+struct Picker<'a> {
+    mode: &'a Mode,
+    item: &'a Item,
+    layers: &'a EditorLayers,
+}
+
+impl Picker<'_> {
+    fn picks(&self, item_info: &ItemInfo) -> bool {
+        self.layers.is_selectable(&item_info.item)
+            && (*self.mode != Mode::SelectSpecific || item_info.item == *self.item)
+    }
+
+    fn items_at<'a>(
+        &'a self,
+        map: &'a Map,
+        cell: &GridPosition,
+    ) -> impl Iterator<Item = &'a ItemInfo> {
+        let cell_pos: Position = cell.into();
+        map.items.iter().filter(move |i| {
+            let pos: Position = i.position.into();
+            cell_pos.distance_to(&pos) < 0.5 && self.picks(i)
+        })
+    }
+
+    /// A click without dragging selects the item under it. With shift, it's added to or removed
+    /// from the selection instead.
+    fn click(&self, map: &Map, cell: &GridPosition, selected: &mut SelectedItems, shift: bool) {
+        let found = self.items_at(map, cell).next().cloned();
+        match (found, shift) {
+            (Some(found), true) => {
+                if selected.contains(&found) {
+                    selected.0.retain(|i| *i != found);
+                } else {
+                    selected.0.push(found);
+                }
+            }
+            (Some(found), false) => selected.0 = vec![found],
+            (None, true) => {}
+            (None, false) => selected.clear(),
+        }
+    }
+
+    /// Selects everything in the box between two corners. With shift, it's added to the
+    /// selection instead of replacing it.
+    fn box_select(
+        &self,
+        map: &Map,
+        start: &GridPosition,
+        end: &GridPosition,
+        selected: &mut SelectedItems,
+        shift: bool,
+    ) {
+        let min = GridPosition::new(start.0.x.min(end.0.x), start.0.y.min(end.0.y));
+        let max = GridPosition::new(start.0.x.max(end.0.x), start.0.y.max(end.0.y));
+        if !shift {
+            selected.clear();
+        }
+        for i in map.items.iter() {
+            let c = i.position.nearest_cell_grid_pos();
+            let inside =
+                c.0.x >= min.0.x && c.0.x <= max.0.x && c.0.y >= min.0.y && c.0.y <= max.0.y;
+            if inside && self.picks(i) && !selected.contains(i) {
+                selected.0.push(i.clone());
+            }
+        }
+    }
+}
+
+/// Moves the selected items, which stay selected. Items moved away from the rest of their prefab
+/// placement are unlinked from it.
+fn move_selection(map: &Map, selected: &mut SelectedItems, offset: &GridPosition) -> EditCommand {
+    let before = selected.0.clone();
+    let mut after = before.clone();
+    map.unlink_partial_placements(&mut after);
+    let after: Vec<ItemInfo> = after.iter().map(|i| moved(i, offset)).collect();
+    selected.0 = after.clone();
+    EditCommand::Replace { before, after }
+}
+
+pub fn click_select(
+    button: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    map: Res<Map>,
+    selection: Query<&Transform, With<Selection>>,
+    mode: Res<Mode>,
+    item: Res<Item>,
+    layers: Res<EditorLayers>,
+    mut selected: ResMut<SelectedItems>,
+    mut drag: Local<Option<SelectDrag>>,
+    mut edits: EventWriter<Edit>,
+    egui_context: Res<EguiContext>,
+) {
+    if *mode != Mode::Select && *mode != Mode::SelectSpecific {
+        *drag = None;
+        return;
+    }
+
+    let cell = cursor_cell(selection.single().unwrap());
+    let shift = shift_pressed(&keys);
+    let picker = Picker {
+        mode: &mode,
+        item: &item,
+        layers: &layers,
+    };
+
+    if button.just_pressed(MouseButton::Left) && !egui_context.ctx().is_pointer_over_area() {
+        let on_selected = picker.items_at(&map, &cell).any(|i| selected.contains(i));
+        *drag = if on_selected && !shift {
+            Some(SelectDrag::Move(cell))
+        } else {
+            Some(SelectDrag::Box(cell))
+        };
+    }
+
+    if !button.just_released(MouseButton::Left) {
+        return;
+    }
+    let start = match drag.take() {
+        Some(d) => d,
+        None => return,
+    };
+
+    match start {
+        SelectDrag::Move(start) if start != cell => {
+            let command = move_selection(&map, &mut selected, &(&cell - &start));
+            edits.send(Edit::Do(command));
+        }
+        SelectDrag::Box(start) if start != cell => {
+            picker.box_select(&map, &start, &cell, &mut selected, shift);
+        }
+        _ => picker.click(&map, &cell, &mut selected, shift),
+    }
+}
+
+/// Delete, R to rotate around the centre of the selection, and Ctrl with C, X or V.
+pub fn selection_keys(
+    keys: Res<Input<KeyCode>>,
+    mode: Res<Mode>,
+    map: Res<Map>,
+    settings: Res<EditorSettings>,
+    layers: Res<EditorLayers>,
+    mut selected: ResMut<SelectedItems>,
+    mut clipboard: ResMut<Clipboard>,
+    mut edits: EventWriter<Edit>,
+    selection: Query<&Transform, With<Selection>>,
+    egui_context: Res<EguiContext>,
+) {
+    // Typing into a text box shouldn't delete things.
+    if egui_context.ctx().wants_keyboard_input() {
+        return;
+    }
+
+    let ctrl = ctrl_pressed(&keys);
+    if ctrl && keys.just_pressed(KeyCode::V) {
+        let cell = cursor_cell(selection.single().unwrap());
+        if let Some(mut pasted) = paste(&clipboard, &cell) {
+            pasted.retain(|i| layers.is_editable(i.item.layer()));
+            map.renumber_placements(&mut pasted);
+            if !pasted.is_empty() {
+                let count = pasted.len();
+                let command = add_items_command(&map, &settings, pasted);
+                // Autotile may rewrite the pasted walls, so select what actually gets added,
+                // which is always the tail of the command's new items.
+                selected.0 = match &command {
+                    EditCommand::Add(items) => items.clone(),
+                    EditCommand::Replace { after, .. } => after[after.len() - count..].to_vec(),
+                    EditCommand::Remove(_) => unreachable!(),
+                };
+                edits.send(Edit::Do(command));
+            }
+        }
+        return;
+    }
+
+    if matches!(*mode, Mode::Add | Mode::Prefab) || selected.0.is_empty() {
+        return;
+    }
+
+    if ctrl && (keys.just_pressed(KeyCode::C) || keys.just_pressed(KeyCode::X)) {
+        let mut copied = selected.0.clone();
+        map.unlink_partial_placements(&mut copied);
+        clipboard.0 = Some(copy(&copied));
+        egui_context.ctx().output().copied_text = clipboard.0.clone().unwrap_or_default();
+    }
+
+    if keys.just_pressed(KeyCode::Delete) || (ctrl && keys.just_pressed(KeyCode::X)) {
+        edits.send(Edit::Do(EditCommand::Remove(selected.0.clone())));
+        selected.clear();
+    } else if keys.just_pressed(KeyCode::R) && !ctrl {
+        let before = selected.0.clone();
+        let mut after = before.clone();
+        map.unlink_partial_placements(&mut after);
+        let after = rotated(&after);
+        edits.send(Edit::Do(EditCommand::Replace {
+            before,
+            after: after.clone(),
+        }));
+        selected.0 = after;
+    }
+}
+
+/// Forget selected items that aren't in the map anymore, e.g. after an undo, or that can't be
+/// selected after the active layer changes.
+pub fn prune_selection(
+    map: Res<Map>,
+    layers: Res<EditorLayers>,
+    mut selected: ResMut<SelectedItems>,
+) {
+    if !map.is_changed() && !layers.is_changed() {
+        return;
+    }
+    let keep = |i: &ItemInfo| map.items.contains(i) && layers.is_selectable(&i.item);
+    if !selected.0.iter().all(keep) {
+        selected.0.retain(keep);
+    }
+}
+
+pub fn highlight_selection(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    selected: Res<SelectedItems>,
+    highlights: Query<Entity, With<SelectionHighlight>>,
+) {
+    if !selected.is_changed() {
+        return;
+    }
+
+    for entity in highlights.iter() {
+        commands.entity(entity).despawn();
+    }
+    let material = materials.add(asset_server.load("cells/selection.png").into());
+    for item_info in &selected.0 {
+        let pos: Position = item_info.position.into();
+        let mut transform = pos.to_transform();
+        transform.translation.z = 4.8;
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: material.clone(),
+                transform,
+                ..Default::default()
+            })
+            .insert(SelectionHighlight);
+    }
+}
+
+fn moved(item_info: &ItemInfo, offset: &GridPosition) -> ItemInfo {
+    let mut new = item_info.clone();
+    new.position = match item_info.position {
+        FlexPosition::Grid(g) => FlexPosition::Grid(&g + offset),
+        FlexPosition::Position(p) => {
+            FlexPosition::Position(Position(p.0 + Vector2::new(offset.0.x as f64, offset.0.y as f64)))
+        }
+    };
+    if let Some(link) = &mut new.properties.prefab {
+        link.origin = &link.origin + offset;
+    }
+    new
+}
+
+/// The cell nearest the middle of the items' bounding box.
+pub fn centre(items: &[ItemInfo]) -> GridPosition {
+    let map = Map::from_items(items.to_vec());
+    match map.bounds() {
+        Some((min, max)) => GridPosition::new((min.0.x + max.0.x) / 2, (min.0.y + max.0.y) / 2),
+        None => GridPosition::zero(),
+    }
+}
+
+/// Rotates 90° anticlockwise around the centre cell, the same direction as `rotate_key`.
+pub fn rotated(items: &[ItemInfo]) -> Vec<ItemInfo> {
+    let c = centre(items);
+    let (cx, cy) = (c.0.x as f64, c.0.y as f64);
+    items
+        .iter()
+        .map(|i| {
+            let mut new = i.clone();
+            new.position = match i.position {
+                FlexPosition::Grid(g) => {
+                    FlexPosition::Grid(GridPosition::new(c.0.x - (g.0.y - c.0.y), c.0.y + (g.0.x - c.0.x)))
+                }
+                FlexPosition::Position(p) => FlexPosition::Position(Position(Vector2::new(
+                    cx - (p.0.y - cy),
+                    cy + (p.0.x - cx),
+                ))),
+            };
+            new.rotation = (i.rotation + 90.0) % 360.0;
+            new.properties.prefab = i.properties.prefab.as_ref().map(|l| l.turned_around(&c));
+            new
+        })
+        .collect()
+}
+
+pub fn copy(items: &[ItemInfo]) -> String {
+    let c = centre(items);
+    let origin = &GridPosition::zero() - &c;
+    let map = Map::from_items(items.iter().map(|i| moved(i, &origin)).collect());
+    serde_json::to_string_pretty(&map).unwrap()
+}
+
+/// The clipboard items, moved so their centre is at `cell`.
+pub fn paste(clipboard: &Clipboard, cell: &GridPosition) -> Option<Vec<ItemInfo>> {
+    let text = clipboard.0.as_ref()?;
+    let map: Map = match serde_json::from_str(text) {
+        Ok(m) => m,
+        Err(e) => {
+            warn!("Could not paste: {}", e);
+            return None;
+        }
+    };
+    Some(map.items.iter().map(|i| moved(i, cell)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefab::{Prefab, PrefabLink};
+    use std::fs::File;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn at(item: Item, x: i32, y: i32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), 0.0)
+    }
+
+    /// A placement of a two item prefab, saved in `dir` so it can be refreshed.
+    fn placed_map(dir: &Path) -> Map {
+        let prefab = Prefab::new(
+            GridPosition::zero(),
+            &[at(Item::Wall, 0, 0), at(Item::Wall, 1, 0)],
+        );
+        let path = dir.join("prefab.json");
+        serde_json::to_writer(File::create(&path).unwrap(), &prefab).unwrap();
+        let link = PrefabLink {
+            path: path.to_string_lossy().to_string(),
+            instance: 0,
+            origin: GridPosition::new(5, 5),
+            quarter_turns: 0,
+            mirrored: false,
+        };
+        Map::from_items(prefab.place(&link))
+    }
+
+    fn cells(map: &Map) -> Vec<GridPosition> {
+        let mut cells: Vec<GridPosition> = map
+            .items
+            .iter()
+            .map(|i| i.position.nearest_cell_grid_pos())
+            .collect();
+        cells.sort_by_key(|c| (c.0.x, c.0.y));
+        cells
+    }
+
+    /// Walls in a diagonal line, a corner, and a floor tile, which isn't on the active layer.
+    fn select_map() -> Map {
+        Map::from_items(vec![
+            at(Item::Wall, 0, 0),
+            at(Item::Wall, 1, 1),
+            at(Item::Wall, 3, 3),
+            at(Item::WallCorner, 0, 1),
+            at(Item::GeneralTile, 1, 0),
+        ])
+    }
+
+    fn picker<'a>(mode: &'a Mode, layers: &'a EditorLayers) -> Picker<'a> {
+        Picker {
+            mode,
+            item: &Item::Wall,
+            layers,
+        }
+    }
+
+    fn cell(x: i32, y: i32) -> GridPosition {
+        GridPosition::new(x, y)
+    }
+
+    #[test]
+    fn box_select() {
+        let map = select_map();
+        let layers = EditorLayers::default();
+        let picker = picker(&Mode::Select, &layers);
+        let mut selected = SelectedItems::default();
+
+        // Corners either way round, and the floor tile is left out.
+        picker.box_select(&map, &cell(1, 1), &cell(0, 0), &mut selected, false);
+        assert_eq!(
+            selected.0,
+            vec![
+                at(Item::Wall, 0, 0),
+                at(Item::Wall, 1, 1),
+                at(Item::WallCorner, 0, 1)
+            ]
+        );
+
+        picker.box_select(&map, &cell(3, 3), &cell(4, 4), &mut selected, true);
+        assert_eq!(selected.0.len(), 4);
+        picker.box_select(&map, &cell(3, 3), &cell(4, 4), &mut selected, false);
+        assert_eq!(selected.0, vec![at(Item::Wall, 3, 3)]);
+
+        let picker = Picker {
+            mode: &Mode::SelectSpecific,
+            ..picker
+        };
+        picker.box_select(&map, &cell(0, 0), &cell(1, 1), &mut selected, false);
+        assert_eq!(selected.0, vec![at(Item::Wall, 0, 0), at(Item::Wall, 1, 1)]);
+    }
+
+    #[test]
+    fn shift_click_toggles() {
+        let map = select_map();
+        let layers = EditorLayers::default();
+        let picker = picker(&Mode::Select, &layers);
+        let mut selected = SelectedItems::default();
+
+        picker.click(&map, &cell(0, 0), &mut selected, false);
+        picker.click(&map, &cell(1, 1), &mut selected, true);
+        assert_eq!(selected.0, vec![at(Item::Wall, 0, 0), at(Item::Wall, 1, 1)]);
+        picker.click(&map, &cell(0, 0), &mut selected, true);
+        assert_eq!(selected.0, vec![at(Item::Wall, 1, 1)]);
+
+        // Clicking nothing keeps the selection with shift, and clears it without.
+        picker.click(&map, &cell(9, 9), &mut selected, true);
+        assert_eq!(selected.0.len(), 1);
+        picker.click(&map, &cell(9, 9), &mut selected, false);
+        assert!(selected.0.is_empty());
+
+        // Items on other layers can't be clicked.
+        picker.click(&map, &cell(1, 0), &mut selected, false);
+        assert!(selected.0.is_empty());
+    }
+
+    #[test]
+    fn moving_a_selection() {
+        let map = select_map();
+        let mut selected = SelectedItems(vec![at(Item::Wall, 0, 0), at(Item::Wall, 1, 1)]);
+        let command = move_selection(&map, &mut selected, &cell(2, -1));
+        let after = vec![at(Item::Wall, 2, -1), at(Item::Wall, 3, 0)];
+        match command {
+            EditCommand::Replace {
+                before,
+                after: moved,
+            } => {
+                assert_eq!(before, vec![at(Item::Wall, 0, 0), at(Item::Wall, 1, 1)]);
+                assert_eq!(moved, after);
+            }
+            other => panic!("Expected a replace, got {:?}", other),
+        }
+        assert_eq!(selected.0, after);
+    }
+
+    #[test]
+    fn centre_and_rotation() {
+        assert_eq!(centre(&[]), GridPosition::zero());
+        let items = vec![
+            at(Item::Wall, 0, 0),
+            at(Item::Wall, 2, 0),
+            at(Item::Wall, 2, 4),
+        ];
+        assert_eq!(centre(&items), cell(1, 2));
+        // Doors count their whole width.
+        assert_eq!(centre(&[at(Item::Door, 10, 0)]), cell(10, 0));
+
+        let line = vec![at(Item::Wall, 0, 0), at(Item::Wall, 2, 0)];
+        let turned = rotated(&line);
+        let mut expected = vec![at(Item::Wall, 1, -1), at(Item::Wall, 1, 1)];
+        for item in expected.iter_mut() {
+            item.rotation = 90.0;
+        }
+        assert_eq!(turned, expected);
+
+        let back = rotated(&rotated(&rotated(&turned)));
+        assert_eq!(back, line);
+    }
+
+    #[test]
+    fn copy_and_paste() {
+        let items = vec![at(Item::Wall, 10, 10), at(Item::Prisoner, 12, 10)];
+        let clipboard = Clipboard(Some(copy(&items)));
+        // Pasted around the cursor, the same way round.
+        assert_eq!(
+            paste(&clipboard, &cell(0, 0)).unwrap(),
+            vec![at(Item::Wall, -1, 0), at(Item::Prisoner, 1, 0)]
+        );
+        assert_eq!(paste(&clipboard, &cell(11, 10)).unwrap(), items);
+
+        assert_eq!(paste(&Clipboard(None), &cell(0, 0)), None);
+        assert_eq!(
+            paste(&Clipboard(Some("not a map".into())), &cell(0, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn moved_placements_stay_moved_after_refresh() {
+        let dir = tempdir().unwrap();
+        let mut map = placed_map(dir.path());
+        let offset = GridPosition::new(3, -2);
+        map.items = map.items.iter().map(|i| moved(i, &offset)).collect();
+        let before = cells(&map);
+        assert!(map.refresh_prefabs().is_empty());
+        assert_eq!(cells(&map), before);
+    }
+
+    #[test]
+    fn turned_placements_stay_turned_after_refresh() {
+        let dir = tempdir().unwrap();
+        let mut map = placed_map(dir.path());
+        map.items = rotated(&map.items);
+        let before = cells(&map);
+        assert!(map.refresh_prefabs().is_empty());
+        assert_eq!(cells(&map), before);
+    }
+
+    #[test]
+    fn pasted_placements_are_kept_after_refresh() {
+        let dir = tempdir().unwrap();
+        let mut map = placed_map(dir.path());
+        let clipboard = Clipboard(Some(copy(&map.items)));
+        let mut pasted = paste(&clipboard, &GridPosition::new(20, 0)).unwrap();
+        map.renumber_placements(&mut pasted);
+        map.items.extend(pasted);
+        let before = cells(&map);
+        assert_eq!(before.len(), 4);
+        assert!(map.refresh_prefabs().is_empty());
+        assert_eq!(cells(&map), before);
+    }
+
+    #[test]
+    fn moving_part_of_a_placement_unlinks_it() {
+        let dir = tempdir().unwrap();
+        let map = placed_map(dir.path());
+        let mut part = SelectedItems(vec![map.items[0].clone()]);
+        move_selection(&map, &mut part, &GridPosition::new(0, 3));
+        assert_eq!(part.0[0].properties.prefab, None);
+
+        let mut whole = SelectedItems(map.items.clone());
+        move_selection(&map, &mut whole, &GridPosition::new(0, 3));
+        assert!(whole.0.iter().all(|i| i.properties.prefab.is_some()));
+    }
+}
@@ -0,0 +1,348 @@
+use super::history::{Edit, EditCommand};
+use super::layers::EditorLayers;
+use super::{EditorSettings, ItemRotation, Mode, Selection};
+use crate::autotile::{autotile_items, with_neighbours};
+use crate::game::GRID_SIZE;
+use crate::map::{Item, ItemInfo, Map};
+use crate::position::{FlexPosition, GridPosition, Position};
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+
+/// How items are placed in `Mode::Add`. Everything except `Single` is drawn by dragging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Single,
+    Line,
+    Rectangle,
+    RectangleOutline,
+    /// An outline of walls with the right corner pieces, regardless of the selected item.
+    Room,
+}
+
+/// The cell where the current drag started.
+#[derive(Debug, Default)]
+pub struct ToolDrag(Option<GridPosition>);
+
+/// Copies of the items that will be placed when the drag finishes.
+pub struct ToolPreview;
+
+/// Adding items, along with any auto-tiling of the walls around them, as one undoable command.
+pub fn add_items_command(map: &Map, settings: &EditorSettings, added: Vec<ItemInfo>) -> EditCommand {
+    if !settings.autotile {
+        return EditCommand::Add(added);
+    }
+
+    let existing = map.items.len();
+    let mut combined = map.items.clone();
+    combined.extend(added);
+    let cells = with_neighbours(combined[existing..].iter().flat_map(|i| i.cells()));
+
+    let mut before = vec![];
+    let mut after = vec![];
+    for (idx, new) in autotile_items(&combined, Some(&cells)) {
+        if idx < existing {
+            before.push(combined[idx].clone());
+            after.push(new);
+        } else {
+            combined[idx] = new;
+        }
+    }
+    after.extend(combined.drain(existing..));
+
+    EditCommand::Replace { before, after }
+}
+
+pub fn cursor_cell(transform: &Transform) -> GridPosition {
+    let pos: Position = (transform.translation.truncate() / GRID_SIZE).into();
+    pos.nearest_cell()
+}
+
+pub fn drag_tool(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    button: Res<Input<MouseButton>>,
+    mode: Res<Mode>,
+    tool: Res<Tool>,
+    item: Res<Item>,
+    item_rotation: Res<ItemRotation>,
+    mut drag: ResMut<ToolDrag>,
+    mut edits: EventWriter<Edit>,
+    (map, settings, layers): (Res<Map>, Res<EditorSettings>, Res<EditorLayers>),
+    mut last_end: Local<Option<GridPosition>>,
+    selection: Query<&Transform, With<Selection>>,
+    previews: Query<Entity, With<ToolPreview>>,
+    egui_context: Res<EguiContext>,
+) {
+    if *mode != Mode::Add || *tool == Tool::Single {
+        drag.0 = None;
+        return;
+    }
+
+    let end = cursor_cell(selection.single().unwrap());
+    if button.just_pressed(MouseButton::Left) && !egui_context.ctx().is_pointer_over_area() {
+        drag.0 = Some(end);
+        *last_end = None;
+    }
+
+    let start = match drag.0 {
+        Some(s) => s,
+        None => return,
+    };
+
+    if button.just_released(MouseButton::Left) {
+        for entity in previews.iter() {
+            commands.entity(entity).despawn();
+        }
+        let mut items = tool_items(*tool, &item, item_rotation.0, &start, &end);
+        // The room tool always adds walls, whatever item is picked.
+        items.retain(|i| layers.is_editable(i.item.layer()));
+        if !items.is_empty() {
+            edits.send(Edit::Do(add_items_command(&map, &settings, items)));
+        }
+        drag.0 = None;
+        return;
+    }
+
+    if *last_end == Some(end) {
+        return;
+    }
+    *last_end = Some(end);
+
+    for entity in previews.iter() {
+        commands.entity(entity).despawn();
+    }
+    for item_info in tool_items(*tool, &item, item_rotation.0, &start, &end) {
+        let material = materials.add(asset_server.load(item_info.item.path()).into());
+        let pos: Position = item_info.position.into();
+        let mut transform = pos.to_transform();
+        transform.translation.z = 4.0;
+        transform.rotation = item_info.quat();
+        commands
+            .spawn_bundle(SpriteBundle {
+                material,
+                transform,
+                ..Default::default()
+            })
+            .insert(ToolPreview);
+    }
+}
+
+pub fn tool_items(
+    tool: Tool,
+    item: &Item,
+    rotation: f32,
+    start: &GridPosition,
+    end: &GridPosition,
+) -> Vec<ItemInfo> {
+    match tool {
+        Tool::Single => vec![item_at(item, start, rotation)],
+        Tool::Line => {
+            let cells = line_cells(start, end);
+            // Walls follow the direction of the line.
+            let vertical = cells.len() > 1 && cells[0].0.x == cells[1].0.x;
+            let rotation = match item {
+                Item::Wall if vertical => 90.0,
+                Item::Wall => 0.0,
+                _ => rotation,
+            };
+            cells
+                .iter()
+                .map(|c| item_at(item, c, rotation))
+                .collect()
+        }
+        Tool::Rectangle => rect_cells(start, end, true)
+            .iter()
+            .map(|c| item_at(item, c, rotation))
+            .collect(),
+        Tool::RectangleOutline => rect_cells(start, end, false)
+            .iter()
+            .map(|c| item_at(item, c, rotation))
+            .collect(),
+        Tool::Room => room_items(start, end),
+    }
+}
+
+fn item_at(item: &Item, cell: &GridPosition, rotation: f32) -> ItemInfo {
+    ItemInfo::new(item.clone(), FlexPosition::Grid(*cell), rotation)
+}
+
+fn min_max(a: &GridPosition, b: &GridPosition) -> (GridPosition, GridPosition) {
+    (
+        GridPosition::new(a.0.x.min(b.0.x), a.0.y.min(b.0.y)),
+        GridPosition::new(a.0.x.max(b.0.x), a.0.y.max(b.0.y)),
+    )
+}
+
+/// A horizontal or vertical line from `start`, along whichever axis was dragged furthest.
+pub fn line_cells(start: &GridPosition, end: &GridPosition) -> Vec<GridPosition> {
+    let diff = end - start;
+    if diff.0.x.abs() >= diff.0.y.abs() {
+        let (min, max) = (start.0.x.min(end.0.x), start.0.x.max(end.0.x));
+        (min..=max).map(|x| GridPosition::new(x, start.0.y)).collect()
+    } else {
+        let (min, max) = (start.0.y.min(end.0.y), start.0.y.max(end.0.y));
+        (min..=max).map(|y| GridPosition::new(start.0.x, y)).collect()
+    }
+}
+
+pub fn rect_cells(a: &GridPosition, b: &GridPosition, filled: bool) -> Vec<GridPosition> {
+    let (min, max) = min_max(a, b);
+    let mut cells = vec![];
+    for x in min.0.x..=max.0.x {
+        for y in min.0.y..=max.0.y {
+            let edge = x == min.0.x || x == max.0.x || y == min.0.y || y == max.0.y;
+            if filled || edge {
+                cells.push(GridPosition::new(x, y));
+            }
+        }
+    }
+    cells
+}
+
+/// Horizontal walls are 0°, vertical walls are 90°, and each corner is rotated so it joins the two
+/// walls next to it.
+pub fn room_items(a: &GridPosition, b: &GridPosition) -> Vec<ItemInfo> {
+    let (min, max) = min_max(a, b);
+
+    // Too thin to have corners.
+    if min.0.x == max.0.x || min.0.y == max.0.y {
+        return tool_items(Tool::Line, &Item::Wall, 0.0, &min, &max);
+    }
+
+    let mut items = vec![
+        item_at(&Item::WallCorner, &GridPosition::new(max.0.x, min.0.y), 0.0),
+        item_at(&Item::WallCorner, &GridPosition::new(max.0.x, max.0.y), 90.0),
+        item_at(&Item::WallCorner, &GridPosition::new(min.0.x, max.0.y), 180.0),
+        item_at(&Item::WallCorner, &GridPosition::new(min.0.x, min.0.y), 270.0),
+    ];
+    for x in (min.0.x + 1)..max.0.x {
+        items.push(item_at(&Item::Wall, &GridPosition::new(x, min.0.y), 0.0));
+        items.push(item_at(&Item::Wall, &GridPosition::new(x, max.0.y), 0.0));
+    }
+    for y in (min.0.y + 1)..max.0.y {
+        items.push(item_at(&Item::Wall, &GridPosition::new(min.0.x, y), 90.0));
+        items.push(item_at(&Item::Wall, &GridPosition::new(max.0.x, y), 90.0));
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autotile::autotile_items;
+
+    fn cells(cells: &[(i32, i32)]) -> Vec<GridPosition> {
+        cells
+            .iter()
+            .map(|(x, y)| GridPosition::new(*x, *y))
+            .collect()
+    }
+
+    fn sorted(mut cells: Vec<GridPosition>) -> Vec<GridPosition> {
+        cells.sort_by_key(|c| (c.0.x, c.0.y));
+        cells
+    }
+
+    fn at(x: i32, y: i32) -> GridPosition {
+        GridPosition::new(x, y)
+    }
+
+    #[test]
+    fn lines_follow_the_longest_axis() {
+        assert_eq!(
+            line_cells(&at(2, 0), &at(4, 0)),
+            cells(&[(2, 0), (3, 0), (4, 0)])
+        );
+        // Reversed lines cover the same cells.
+        assert_eq!(
+            line_cells(&at(4, 0), &at(2, 0)),
+            cells(&[(2, 0), (3, 0), (4, 0)])
+        );
+        assert_eq!(
+            line_cells(&at(1, 1), &at(1, -1)),
+            cells(&[(1, -1), (1, 0), (1, 1)])
+        );
+        // Diagonal drags stay on the start's row or column.
+        assert_eq!(
+            line_cells(&at(0, 0), &at(2, 1)),
+            cells(&[(0, 0), (1, 0), (2, 0)])
+        );
+        assert_eq!(
+            line_cells(&at(0, 0), &at(-1, -2)),
+            cells(&[(0, -2), (0, -1), (0, 0)])
+        );
+        // Exactly diagonal is horizontal.
+        assert_eq!(line_cells(&at(0, 0), &at(1, 1)), cells(&[(0, 0), (1, 0)]));
+        assert_eq!(line_cells(&at(3, 3), &at(3, 3)), cells(&[(3, 3)]));
+    }
+
+    #[test]
+    fn rectangles() {
+        let outline = sorted(rect_cells(&at(0, 0), &at(2, 2), false));
+        let filled = sorted(rect_cells(&at(0, 0), &at(2, 2), true));
+        assert_eq!(outline.len(), 8);
+        assert!(!outline.contains(&at(1, 1)));
+        assert_eq!(filled.len(), 9);
+        assert!(outline.iter().all(|c| filled.contains(c)));
+        // Either pair of corners gives the same rectangle.
+        assert_eq!(sorted(rect_cells(&at(2, 0), &at(0, 2), false)), outline);
+    }
+
+    #[test]
+    fn small_rectangles() {
+        for filled in [false, true].iter() {
+            assert_eq!(rect_cells(&at(5, 5), &at(5, 5), *filled), cells(&[(5, 5)]));
+            // Too thin to have an inside, so the outline is filled too.
+            assert_eq!(
+                sorted(rect_cells(&at(0, 3), &at(0, 1), *filled)),
+                cells(&[(0, 1), (0, 2), (0, 3)])
+            );
+        }
+    }
+
+    fn wall(x: i32, y: i32, rotation: f32) -> ItemInfo {
+        item_at(&Item::Wall, &at(x, y), rotation)
+    }
+
+    #[test]
+    fn rooms_are_already_tiled() {
+        let items = room_items(&at(2, 2), &at(0, 0));
+        assert_eq!(items.len(), 8);
+        assert!(items.contains(&item_at(&Item::WallCorner, &at(0, 0), 270.0)));
+        assert!(items.contains(&wall(1, 0, 0.0)));
+        assert!(items.contains(&wall(0, 1, 90.0)));
+        assert!(autotile_items(&items, None).is_empty());
+
+        // Thin rooms are a line of walls.
+        assert_eq!(
+            room_items(&at(0, 0), &at(0, 2)),
+            vec![wall(0, 0, 90.0), wall(0, 1, 90.0), wall(0, 2, 90.0)]
+        );
+    }
+
+    #[test]
+    fn rooms_retile_the_walls_they_touch() {
+        // A wall on its own above where the room's top wall will be.
+        let map = Map::from_items(vec![wall(1, 3, 0.0)]);
+        let room = room_items(&at(0, 0), &at(2, 2));
+        match add_items_command(&map, &EditorSettings::default(), room.clone()) {
+            EditCommand::Replace { before, after } => {
+                assert_eq!(before, vec![wall(1, 3, 0.0)]);
+                assert_eq!(after[0], wall(1, 3, 90.0));
+                // The top wall is now a T, which stays straight.
+                assert_eq!(&after[1..], &room[..]);
+            }
+            other => panic!("Expected a replace, got {:?}", other),
+        }
+
+        let settings = EditorSettings {
+            autotile: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            add_items_command(&map, &settings, room),
+            EditCommand::Add(_)
+        ));
+    }
+}
@@ -1,24 +1,28 @@
-use std::fs::File;
-use std::ops::{Add, Deref, Sub};
-use bevy::core::FixedTimestep;
+use std::path::PathBuf;
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
-use bevy::render::camera::Camera;
-use bevy::utils::HashMap;
+use bevy::utils::HashSet;
 use bevy_egui::egui::FontDefinitions;
 use bevy_egui::{egui, EguiContext};
-use nalgebra::Vector2;
 use rand::prelude::IteratorRandom;
-use rand::{thread_rng, Rng, RngCore};
+use rand::{thread_rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-use crate::input::exit_on_escape_key;
+use crate::autotile::autotile;
+use crate::balance::{Balance, Difficulty};
+use crate::collision::{check_body_collisions, Body};
+use crate::debug::{DebugOverlay, OverlayMaterials};
 use crate::map::{Item, ItemInfo, Map, PathfindingMap};
 use crate::path::Path;
 use crate::position::{
     apply_velocity, check_velocity_collisions, sync_sprite_positions, Direction, GridPosition,
     Position, Speed, Velocity,
 };
-use crate::wires::{Smoking, Wire};
-use crate::{path, player, wires, AppState};
+use crate::rooms::{InRoom, OutsideCell, Rooms};
+use crate::scripting::LevelScript;
+use crate::triggers::{LevelTriggers, Notifications};
+use crate::wires::{Circuit, Wire};
+use crate::{debug, input, path, player, rooms, scripting, triggers, wires, AppState};
 
 pub const GRID_SIZE: f32 = 160.0;
 
@@ -28,6 +32,7 @@ struct FixedUpdateStage;
 #[derive(Clone, Hash, Debug, PartialEq, Eq, SystemLabel)]
 enum Label {
     Setup,
+    CheckBodyCollisions,
     CheckVelocityCollisions,
     ApplyVelocity,
     ClearActions,
@@ -37,34 +42,67 @@ pub struct Game;
 
 impl Plugin for Game {
     fn build(&self, app: &mut AppBuilder) {
+        let lockstep = app
+            .app
+            .world
+            .get_resource::<Lockstep>()
+            .is_some_and(|l| l.0);
+        let fixed_stage = if lockstep {
+            SystemStage::parallel()
+        } else {
+            SystemStage::parallel().with_run_criteria(fixed_timestep.system())
+        };
+
         app.insert_resource(PathfindingMap::new())
+            .insert_resource(Balance::load_default())
+            .init_resource::<Difficulty>()
+            .insert_resource(Rooms::new())
+            .init_resource::<DebugOverlay>()
+            .init_resource::<OverlayMaterials>()
+            .init_resource::<LevelTriggers>()
+            .init_resource::<Notifications>()
+            .init_resource::<LevelScript>()
             //
+            .init_resource::<Level>()
+            .init_resource::<Playtest>()
+            .init_resource::<Headless>()
+            .init_resource::<GameRng>()
             .add_system_set(
                 SystemSet::on_enter(AppState::InGame)
                     .with_system(setup.system().label(Label::Setup)),
             )
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup.system()))
             .add_system_set(
                 SystemSet::on_update(AppState::InGame)
                     .with_system(ui.system())
+                    .with_system(triggers::notifications_ui.system())
+                    .with_system(debug::toggle_overlay.system())
+                    .with_system(debug::draw_game_overlay.system())
                     // This is here because it uses `just_pressed` which will be skipped in the
                     // FixedUpdateStage.
-                    .with_system(player::player_keyboard_action.system()),
+                    .with_system(player::player_keyboard_action.system())
+                    .with_system(back_to_editor_key.system()),
             )
             .add_stage_after(
                 CoreStage::Update,
                 FixedUpdateStage,
-                SystemStage::parallel()
-                    // https://github.com/bevyengine/bevy/blob/latest/examples/ecs/fixed_timestep.rs
-                    .with_run_criteria(FixedTimestep::step(1f64 / 60f64))
+                fixed_stage
+                    .with_system(input::exit_after_steps.system())
                     .with_system(
                         player::player_keyboard_movement
                             .system()
-                            .before(Label::CheckVelocityCollisions),
+                            .before(Label::CheckBodyCollisions),
                     )
                     .with_system(player::chase_camera.system())
                     .with_system(
                         path::move_along_path
                             .system()
+                            .before(Label::CheckBodyCollisions),
+                    )
+                    .with_system(
+                        check_body_collisions
+                            .system()
+                            .label(Label::CheckBodyCollisions)
                             .before(Label::CheckVelocityCollisions),
                     )
                     .with_system(
@@ -80,12 +118,15 @@ impl Plugin for Game {
                     )
                     .with_system(sync_sprite_positions.system().after(Label::ApplyVelocity))
                     .with_system(prisoner_escape.system())
+                    .with_system(rooms::track_prisoner_rooms.system())
                     //
                     .with_system(wires::damaged_check_if_broken.system())
                     .with_system(wires::damage_wires.system())
                     .with_system(wires::damaged_smoke.system())
                     .with_system(wires::move_smoke.system())
                     .with_system(wires::open_doors_if_any_wires_are_broken.system())
+                    .with_system(triggers::run_triggers.system())
+                    .with_system(scripting::run_scripts.system())
                     // Actions
                     .with_system(player::warden_actions.system().before(Label::ClearActions))
                     .with_system(player::clear_actions.system().label(Label::ClearActions)),
@@ -93,6 +134,91 @@ impl Plugin for Game {
     }
 }
 
+/// Like `FixedTimestep`, but reads the step from `Balance` each frame, since each level can have
+/// its own. Runs the stage once for each whole step that has passed.
+/// https://github.com/bevyengine/bevy/blob/latest/examples/ecs/fixed_timestep.rs
+fn fixed_timestep(
+    time: Res<Time>,
+    balance: Res<Balance>,
+    mut state: Local<(f64, bool)>,
+) -> ShouldRun {
+    let (accumulator, looping) = &mut *state;
+    // Called again after each run in the same frame, which shouldn't count the frame twice.
+    if !*looping {
+        *accumulator += time.delta_seconds_f64();
+    }
+    if *accumulator >= balance.fixed_step {
+        *accumulator -= balance.fixed_step;
+        *looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        *looping = false;
+        ShouldRun::No
+    }
+}
+
+/// The level `setup` loads. When `map` is set it's used instead of reading `path`, e.g. when
+/// playtesting from the editor. `path` is still used to find the balance file and script.
+/// Generated maps have no path, so they use the default balance file and no script.
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub path: Option<PathBuf>,
+    pub map: Option<Map>,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            path: Some("assets/maps/level1.json".into()),
+            map: None,
+        }
+    }
+}
+
+impl Level {
+    pub fn generated(map: Map) -> Self {
+        Self {
+            path: None,
+            map: Some(map),
+        }
+    }
+}
+
+/// Running without a window, so there's no UI to draw. See `--headless`.
+#[derive(Debug, Default)]
+pub struct Headless(pub bool);
+
+/// Runs exactly one fixed step per frame instead of following the clock, so headless runs,
+/// recordings and replays step the same way every time. Read when the plugin is built.
+#[derive(Debug, Default)]
+pub struct Lockstep(pub bool);
+
+/// The randomness gameplay uses, like prisoner speeds and which wire gets damaged. It's seeded, and
+/// the seed is saved with recordings, so replays play out the same way.
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(thread_rng().next_u64())
+    }
+}
+
+/// Set when the game was started from the editor, so Escape goes back to it.
+#[derive(Debug, Default)]
+pub struct Playtest(pub bool);
+
 #[derive(Debug)]
 pub struct KeyboardControl;
 
@@ -102,10 +228,6 @@ pub struct Warden;
 #[derive(Debug)]
 pub struct Prisoner;
 
-// /// It's the area of a prisoner's room. It is used to know what is outside room or not.
-// #[derive(Debug)]
-// pub struct PrisonRoom;
-//
 #[derive(Debug)]
 pub struct Escaping;
 
@@ -122,55 +244,47 @@ struct Exit;
 
 fn setup(
     mut commands: Commands,
-    mut egui_context: ResMut<EguiContext>,
+    egui_context: ResMut<EguiContext>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut pathfinding_map: ResMut<PathfindingMap>,
     asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
+    level: Res<Level>,
+    headless: Res<Headless>,
+    mut rng: ResMut<GameRng>,
 ) {
     let mut camera = OrthographicCameraBundle::new_2d();
     camera.transform.scale = Vec3::new(8.0, 8.0, 1.0);
     commands.spawn_bundle(camera);
 
-    let fonts = FontDefinitions::default();
-    egui_context.ctx().set_fonts(fonts);
+    if !headless.0 {
+        let fonts = FontDefinitions::default();
+        egui_context.ctx().set_fonts(fonts);
 
-    let style: egui::Style = egui::Style::default();
-    egui_context.ctx().set_style(style);
+        let style: egui::Style = egui::Style::default();
+        egui_context.ctx().set_style(style);
+    }
 
-    let mut f = File::open("assets/maps/level1.json").expect("Could not open file for reading.");
-    let map: Map = serde_json::from_reader(f).expect("Could not read from file.");
+    let mut map: Map = match (&level.map, &level.path) {
+        (Some(map), _) => map.clone(),
+        (None, Some(path)) => Map::load(path).expect("Could not load the map."),
+        (None, None) => panic!("A level needs a map or a path."),
+    };
+    autotile(&mut map);
+    let balance = Balance::load_for_level(level.path.as_deref(), *difficulty);
 
-    let mut min = GridPosition::zero();
-    let mut max = GridPosition::zero();
-    let mut first = false;
-    let mut items_added: HashMap<GridPosition, ()> = HashMap::default();
+    let mut door_cells: HashSet<GridPosition> = HashSet::default();
+    let mut spawn_cells: HashSet<GridPosition> = HashSet::default();
+    let mut exit_cells: HashSet<GridPosition> = HashSet::default();
 
     for item_info in &map.items {
         let grid_pos = item_info.position.nearest_cell_grid_pos();
         let pos: Position = item_info.position.into();
-        if first {
-            min = grid_pos.clone();
-            max = grid_pos.clone();
-            first = false;
-        } else {
-            if min.0.x > grid_pos.0.x {
-                min.0.x = grid_pos.0.x;
-            }
-            if min.0.y > grid_pos.0.y {
-                min.0.y = grid_pos.0.y;
-            }
-            if max.0.x < grid_pos.0.x {
-                max.0.x = grid_pos.0.x;
-            }
-            if max.0.y < grid_pos.0.y {
-                max.0.y = grid_pos.0.y;
-            }
-        }
-
-        let mut walkable = true;
 
         let handle = materials.add(asset_server.load(item_info.item.path()).into());
-        let mut ent = commands.spawn_bundle(sprite(handle, &grid_pos));
+        let mut bundle = sprite(handle, &grid_pos);
+        bundle.transform.translation.z = item_info.item.layer().z();
+        let mut ent = commands.spawn_bundle(bundle);
         ent.insert(pos).insert(item_info.clone());
 
         match &item_info.item {
@@ -183,7 +297,8 @@ fn setup(
                     .insert(Direction::new())
                     .insert(Velocity::zero())
                     .insert(Warden)
-                    .insert(Speed::good_guy())
+                    .insert(Speed::good_guy(&balance))
+                    .insert(Body::warden())
                     .insert(KeyboardControl);
             }
             Item::Prisoner => {
@@ -191,26 +306,38 @@ fn setup(
                     .insert(pos)
                     .insert(Velocity::zero())
                     .insert(Prisoner)
-                    .insert(SpawnPoint(grid_pos.clone()))
-                    .insert(Speed::bad_guy());
+                    .insert(SpawnPoint(grid_pos))
+                    .insert(Speed::bad_guy(&balance, &mut rng.rng))
+                    .insert(Body::prisoner());
+                spawn_cells.insert(grid_pos);
             }
             Item::Wall => {
                 ent.insert(grid_pos);
-                walkable = false;
             }
             Item::WallCorner => {
                 ent.insert(grid_pos);
-                walkable = false;
             }
             Item::Door => {
-                ent.insert(grid_pos).insert(Door(false));
-                walkable = false;
+                let open = item_info.properties.door_open;
+                ent.insert(grid_pos)
+                    .insert(Door(open))
+                    .insert(Circuit(item_info.properties.circuit))
+                    .insert(Visible {
+                        is_visible: !open,
+                        is_transparent: false,
+                    });
+                for delta in &item_info.shape().0 {
+                    door_cells.insert(&grid_pos + delta);
+                }
             }
             Item::Exit => {
                 ent.insert(grid_pos).insert(Exit);
+                exit_cells.insert(grid_pos);
             }
             Item::Wire => {
-                ent.insert(grid_pos).insert(Wire);
+                ent.insert(grid_pos)
+                    .insert(Wire)
+                    .insert(Circuit(item_info.properties.circuit));
             }
             Item::GeneralTile => {
                 ent.insert(grid_pos);
@@ -219,43 +346,97 @@ fn setup(
                 ent.insert(grid_pos);
             }
         };
+    }
 
-        // Fill out the "shape" of the item
-        for delta in &item_info.shape().0 {
-            let delta_cell = &grid_pos + delta;
-            if walkable == false {
-                items_added.insert(delta_cell, ());
-                pathfinding_map.walkable_cells.insert(delta_cell, false);
-            }
-        }
+    *pathfinding_map = PathfindingMap::from_map(&map);
+    commands.insert_resource(balance);
+    commands.insert_resource(LevelTriggers::new(map.triggers.clone()));
+    commands.insert_resource(Notifications::default());
+    commands.insert_resource(
+        level
+            .path
+            .as_deref()
+            .map_or_else(LevelScript::default, LevelScript::for_map),
+    );
+
+    commands.insert_resource(Rooms::detect(
+        &pathfinding_map,
+        &door_cells,
+        &spawn_cells,
+        &exit_cells,
+    ));
+}
+
+/// Everything is respawned by `setup`, so each playtest starts from scratch.
+fn cleanup(
+    mut commands: Commands,
+    mut pathfinding_map: ResMut<PathfindingMap>,
+    mut rooms: ResMut<Rooms>,
+    mut triggers: ResMut<LevelTriggers>,
+    mut script: ResMut<LevelScript>,
+    entities: Query<Entity>,
+) {
+    debug!("Cleanup.");
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
     }
+    *pathfinding_map = PathfindingMap::new();
+    *rooms = Rooms::new();
+    // The fixed update stage keeps running outside the game.
+    *triggers = LevelTriggers::default();
+    *script = LevelScript::default();
+}
 
-    for x in min.0.x..max.0.x {
-        for y in min.0.y..max.0.y {
-            let cell = GridPosition::new(x, y);
-            if items_added.contains_key(&cell) {
-                continue;
-            }
-            pathfinding_map.walkable_cells.insert(cell.clone(), true);
-        }
+fn back_to_editor_key(
+    keys: Res<Input<KeyCode>>,
+    playtest: Res<Playtest>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if playtest.0 && keys.just_pressed(KeyCode::Escape) {
+        state.set(AppState::Editor).unwrap();
     }
 }
 
 fn ui(
     egui_context: ResMut<EguiContext>,
     wardens: Query<(&Position, &Direction), With<Warden>>,
-    prisoners: Query<&Position, With<Prisoner>>,
+    prisoners: Query<(&Position, Option<&InRoom>, Option<&OutsideCell>), With<Prisoner>>,
+    rooms: Res<Rooms>,
+    mut overlay: ResMut<DebugOverlay>,
+    playtest: Res<Playtest>,
+    mut state: ResMut<State<AppState>>,
+    headless: Res<Headless>,
 ) {
+    if headless.0 {
+        return;
+    }
     egui::Window::new("Debug").show(egui_context.ctx(), |ui| {
+        if playtest.0 && ui.button("Back to editor (Esc)").clicked() {
+            state.set(AppState::Editor).unwrap();
+        }
+        ui.checkbox(&mut overlay.0, "Pathfinding overlay (F3)");
+
         for (pos, dir) in wardens.iter() {
             ui.heading("Warden");
             ui.label(format!("{:?}", dir));
             ui.label(format!("{:?}", pos));
         }
 
-        for pos in prisoners.iter() {
+        for (pos, in_room, outside_cell) in prisoners.iter() {
             ui.heading("Prisoner");
             ui.label(format!("{:?}", pos));
+            if let Some(in_room) = in_room {
+                let room = &rooms.rooms[in_room.0 .0];
+                ui.label(format!(
+                    "In room {} ({:?}, {} cells)",
+                    room.id.0,
+                    room.kind,
+                    room.cells.len()
+                ));
+            }
+            if outside_cell.is_some() {
+                ui.label("Outside their cell!");
+            }
         }
     });
 }
@@ -298,17 +479,17 @@ fn prisoner_escape(
     map: Res<PathfindingMap>,
     query: Query<(Entity, &Prisoner, &Position), Without<Path>>,
     exits: Query<(&Exit, &GridPosition)>,
+    mut rng: ResMut<GameRng>,
 ) {
-    let mut rng = thread_rng();
-    let exit_cells = exits.iter().choose_multiple(&mut rng, 1);
-    let exit_cell = exit_cells.get(0);
+    let exit_cells = exits.iter().choose_multiple(&mut rng.rng, 1);
+    let exit_cell = exit_cells.first();
     if exit_cell.is_none() {
         // warn!("No exits found!");
         return;
     }
     let (_, exit_cell) = exit_cell.unwrap();
     for (entity, _prisoner, pos) in query.iter() {
-        let found = map.find_path(&pos.nearest_cell(), &exit_cell);
+        let found = map.find_path(&pos.nearest_cell(), exit_cell);
         if let Some((ref steps, _)) = found {
             // info!("found path {:?}", found);
             commands
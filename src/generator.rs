@@ -0,0 +1,329 @@
+use crate::autotile::autotile;
+use crate::map::{Item, ItemInfo, Map, PathfindingMap};
+use crate::position::{FlexPosition, GridPosition};
+use bevy::utils::HashSet;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Doors are five cells wide, so cells need at least this much room inside.
+const MIN_CELL_WIDTH: i32 = 5;
+const MAX_CELL_WIDTH: i32 = 7;
+const MIN_CELL_HEIGHT: i32 = 3;
+const MAX_CELL_HEIGHT: i32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct GeneratorParams {
+    pub cell_blocks: u32,
+    pub cells_per_block: u32,
+    /// In cells, for both the corridors in front of each block and the hall joining them.
+    pub corridor_width: i32,
+    pub exits: u32,
+    /// How much of each corridor has wire running along it, from 0 to 1.
+    pub wire_density: f32,
+}
+
+impl Default for GeneratorParams {
+    fn default() -> Self {
+        Self {
+            cell_blocks: 3,
+            cells_per_block: 4,
+            corridor_width: 3,
+            exits: 1,
+            wire_density: 0.5,
+        }
+    }
+}
+
+impl GeneratorParams {
+    fn check(&self) -> Result<(), String> {
+        if self.cell_blocks == 0 || self.cells_per_block == 0 {
+            return Err("There must be at least one cell block with one cell.".into());
+        }
+        if self.corridor_width < 1 {
+            return Err("Corridors must be at least one cell wide.".into());
+        }
+        if self.exits == 0 {
+            return Err("There must be at least one exit.".into());
+        }
+        if !(0.0..=1.0).contains(&self.wire_density) {
+            return Err("Wire density must be between 0 and 1.".into());
+        }
+        Ok(())
+    }
+}
+
+/// Rows of cell blocks, each with its doors facing the corridor above it, and a hall down the
+/// left joining the corridors. Each block's doors and wires are on their own circuit.
+///
+/// The same seed and parameters always make the same map, on any platform and with any build,
+/// since `ChaCha8Rng` always gives the same numbers for a seed. The result passes
+/// `check_reachability`.
+pub fn generate(seed: u64, params: &GeneratorParams) -> Result<Map, String> {
+    params.check()?;
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let cw = params.corridor_width;
+    let cells = params.cells_per_block as i32;
+
+    let mut walls: HashSet<GridPosition> = HashSet::default();
+    let mut items = vec![];
+    let mut corridors = vec![];
+    // Left edge of the cell blocks, past the hall.
+    let x0 = cw + 1;
+    let sizes: Vec<(i32, i32)> = (0..params.cell_blocks)
+        .map(|_| {
+            (
+                rng.gen_range(MIN_CELL_WIDTH..=MAX_CELL_WIDTH),
+                rng.gen_range(MIN_CELL_HEIGHT..=MAX_CELL_HEIGHT),
+            )
+        })
+        .collect();
+    // The right outer wall, past the widest block.
+    let right = sizes
+        .iter()
+        .map(|(w, _)| x0 + cells * (w + 1))
+        .max()
+        .unwrap();
+    let mut y0 = 0;
+
+    for (block, (w, h)) in sizes.into_iter().enumerate() {
+        let block = block as u32;
+        let door_y = y0 + h + 1;
+
+        // Narrower blocks are walled off from the right outer wall.
+        for x in x0..=right {
+            walls.insert(GridPosition::new(x, y0));
+            walls.insert(GridPosition::new(x, door_y));
+        }
+        for k in 0..=cells {
+            for y in y0..=door_y {
+                walls.insert(GridPosition::new(x0 + k * (w + 1), y));
+            }
+        }
+
+        for k in 0..cells {
+            let left = x0 + k * (w + 1) + 1;
+            for x in left..left + w {
+                for y in (y0 + 1)..door_y {
+                    items.push(at(Item::CellTile, x, y, 0.0));
+                }
+            }
+            let centre = left + w / 2;
+            items.push(at(Item::Prisoner, centre, y0 + 1 + h / 2, 0.0));
+
+            let mut door = at(Item::Door, centre, door_y, 0.0);
+            door.properties.circuit = block;
+            for cell in door.cells() {
+                walls.remove(&cell);
+            }
+            items.push(door);
+        }
+
+        corridors.push((block, door_y + 1));
+        y0 = door_y + cw + 1;
+    }
+
+    let top = y0;
+    for x in 0..=right {
+        walls.insert(GridPosition::new(x, 0));
+        walls.insert(GridPosition::new(x, top));
+    }
+    for y in 0..=top {
+        walls.insert(GridPosition::new(0, y));
+        walls.insert(GridPosition::new(right, y));
+    }
+
+    for x in 1..right {
+        for y in 1..top {
+            let cell = GridPosition::new(x, y);
+            let in_corridor = corridors
+                .iter()
+                .any(|(_, bottom)| y >= *bottom && y < bottom + cw);
+            if (x <= cw || in_corridor) && !walls.contains(&cell) {
+                items.push(at(Item::GeneralTile, x, y, 0.0));
+            }
+        }
+    }
+
+    // Wires run along the side of each corridor away from the doors.
+    let corridor_length = right - x0;
+    let run = ((corridor_length as f32) * params.wire_density).round() as i32;
+    for (block, bottom) in &corridors {
+        items.push(at(Item::Warden, x0, *bottom, 0.0));
+        if run == 0 {
+            continue;
+        }
+        let start = x0 + rng.gen_range(0..=corridor_length - run);
+        for x in start..start + run {
+            let mut wire = at(Item::Wire, x, bottom + cw - 1, 0.0);
+            wire.properties.circuit = *block;
+            items.push(wire);
+        }
+    }
+
+    let mut exit_cells: Vec<GridPosition> = corridors
+        .iter()
+        .map(|(_, bottom)| GridPosition::new(right - 1, bottom + cw / 2))
+        .collect();
+    exit_cells.push(GridPosition::new(1, top - 1));
+    exit_cells.push(GridPosition::new(1, 1));
+    if params.exits as usize > exit_cells.len() {
+        return Err(format!(
+            "At most {} exits fit with {} cell blocks.",
+            exit_cells.len(),
+            params.cell_blocks
+        ));
+    }
+    for cell in exit_cells.choose_multiple(&mut rng, params.exits as usize) {
+        items.push(at(Item::Exit, cell.0.x, cell.0.y, 0.0));
+    }
+
+    let mut wall_cells: Vec<&GridPosition> = walls.iter().collect();
+    // Sort so the item order, and so the map file, is the same for the same seed.
+    wall_cells.sort_by_key(|c| (c.0.x, c.0.y));
+    items.extend(wall_cells.iter().map(|c| at(Item::Wall, c.0.x, c.0.y, 0.0)));
+
+    let mut map = Map::from_items(items);
+    autotile(&mut map);
+    check_reachability(&map)?;
+    Ok(map)
+}
+
+fn at(item: Item, x: i32, y: i32, rotation: f32) -> ItemInfo {
+    ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), rotation)
+}
+
+/// Checks that:
+/// - there is at least one prisoner, warden and exit,
+/// - with the doors closed, no prisoner can walk to an exit,
+/// - with the doors open, every prisoner and warden can walk to an exit.
+pub fn check_reachability(map: &Map) -> Result<(), String> {
+    let cells_of = |item: Item| -> Vec<GridPosition> {
+        map.items
+            .iter()
+            .filter(|i| i.item == item)
+            .map(|i| i.position.nearest_cell_grid_pos())
+            .collect()
+    };
+    let prisoners = cells_of(Item::Prisoner);
+    let wardens = cells_of(Item::Warden);
+    let exits: HashSet<GridPosition> = cells_of(Item::Exit).into_iter().collect();
+    if prisoners.is_empty() || wardens.is_empty() || exits.is_empty() {
+        return Err("Maps need at least one prisoner, warden and exit.".into());
+    }
+
+    // Walking works the same both ways, so it's enough to walk from each exit instead of from
+    // every prisoner and warden.
+    let from_exits = |pathfinding_map: &PathfindingMap| -> HashSet<GridPosition> {
+        exits
+            .iter()
+            .flat_map(|e| pathfinding_map.reachable_from(e))
+            .collect()
+    };
+
+    let closed = from_exits(&PathfindingMap::from_map(map));
+    for prisoner in &prisoners {
+        if closed.contains(prisoner) {
+            return Err(format!(
+                "The prisoner at {:?} can reach an exit with the doors closed.",
+                prisoner.0
+            ));
+        }
+    }
+
+    let mut opened = map.clone();
+    for item in opened.items.iter_mut().filter(|i| i.item == Item::Door) {
+        item.properties.door_open = true;
+    }
+    let open = from_exits(&PathfindingMap::from_map(&opened));
+    for spawn in prisoners.iter().chain(wardens.iter()) {
+        if !open.contains(spawn) {
+            return Err(format!(
+                "Nothing at {:?} can reach an exit, even with the doors open.",
+                spawn.0
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint;
+
+    fn extremes() -> Vec<GeneratorParams> {
+        vec![
+            GeneratorParams::default(),
+            GeneratorParams {
+                cell_blocks: 1,
+                cells_per_block: 1,
+                corridor_width: 1,
+                exits: 1,
+                wire_density: 0.0,
+            },
+            GeneratorParams {
+                cell_blocks: 4,
+                cells_per_block: 6,
+                corridor_width: 4,
+                // One per corridor, and two in the hall.
+                exits: 6,
+                wire_density: 1.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn every_seed_makes_a_clean_map() {
+        for params in extremes() {
+            for seed in 0..100 {
+                let map = generate(seed, &params)
+                    .unwrap_or_else(|e| panic!("Seed {} with {:?}: {}", seed, params, e));
+                let diagnostics = lint(&map);
+                assert!(
+                    diagnostics.is_empty(),
+                    "Seed {} with {:?}: {:?}",
+                    seed,
+                    params,
+                    diagnostics
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_map() {
+        for params in extremes() {
+            for seed in 0..100 {
+                let first = generate(seed, &params).unwrap();
+                let second = generate(seed, &params).unwrap();
+                assert_eq!(first.items, second.items, "Seed {}", seed);
+            }
+        }
+    }
+
+    /// FNV-1a, which unlike `DefaultHasher` is the same in every Rust release.
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Fails if anything changes the maps a seed makes, like a new `rand` version. Seeds shared by
+    /// players would make different prisons, so only update the hash on purpose.
+    #[test]
+    fn seeds_keep_making_the_same_map() {
+        let map = generate(1234, &GeneratorParams::default()).unwrap();
+        let json = serde_json::to_vec(&map.items).unwrap();
+        assert_eq!(fnv1a(&json), 2846875746962615555);
+    }
+
+    #[test]
+    fn rejects_too_many_exits() {
+        let params = GeneratorParams {
+            exits: 6,
+            ..GeneratorParams::default()
+        };
+        assert!(generate(0, &params).is_err());
+    }
+}
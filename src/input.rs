@@ -1,11 +1,21 @@
 use bevy::app::{AppExit, Events};
 use bevy::prelude::*;
 
-pub fn exit_on_escape_key(
+/// Fixed steps left before exiting, for running without a window.
+pub struct ExitAfter(pub u32);
+
+/// Runs in the fixed update stage, so `--headless` counts game steps rather than frames.
+pub fn exit_after_steps(
     mut app_exit_events: ResMut<Events<bevy::app::AppExit>>,
-    keys: Res<Input<KeyCode>>,
+    exit_after: Option<ResMut<ExitAfter>>,
 ) {
-    if keys.just_pressed(KeyCode::Escape) {
+    let mut exit_after = match exit_after {
+        Some(e) => e,
+        None => return,
+    };
+    if exit_after.0 == 0 {
         app_exit_events.send(AppExit);
+        return;
     }
+    exit_after.0 -= 1;
 }
@@ -1,21 +1,53 @@
+// Bevy systems take their resources and queries as arguments.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+pub mod ascii;
+mod autotile;
+mod balance;
+mod cli;
+mod collision;
+mod debug;
 mod editor;
 mod game;
+mod generator;
 mod input;
-mod map;
+pub mod lint;
+pub mod map;
+mod map_image;
 mod menus;
 mod path;
 mod player;
-mod position;
+pub mod position;
+pub mod prefab;
+mod replay;
+mod rooms;
+mod scripting;
+pub mod tiled;
+pub mod triggers;
 mod wires;
 
-use crate::editor::Editor;
-use crate::game::Game;
+use crate::balance::Balance;
+use crate::editor::{Editor, OpenOnStart};
+use crate::game::{Game, GameRng, Headless, Level, Lockstep};
+use crate::generator::generate;
+use crate::input::ExitAfter;
+use crate::map::Map;
 use crate::menus::MainMenu;
-use bevy::core::FixedTimestep;
+use crate::replay::{Recorder, Recording, Replay};
+use bevy::app::ScheduleRunnerSettings;
+use bevy::asset::AssetPlugin;
+use bevy::input::{InputPlugin, InputSystem};
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
+use bevy::render::renderer::{HeadlessRenderResourceContext, RenderResourceContext};
+use bevy::render::RenderPlugin;
+use bevy::sprite::SpritePlugin;
+use bevy::transform::TransformPlugin;
+use bevy::window::{WindowMode, WindowPlugin};
 use bevy_egui::EguiPlugin;
 use slowchop::{SplashScreen, SplashScreenState};
-use std::env;
+use std::time::Duration;
+use std::{env, process};
 use wasm_bindgen::prelude::*;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -29,35 +61,149 @@ pub enum AppState {
 
 #[wasm_bindgen]
 pub fn run() {
-    let initial_app_state = match env::args().skip(1).next().as_deref() {
-        Some("solo") => AppState::InGame,
-        Some("editor") => AppState::Editor,
-        Some(x) => panic!("Unknown argument: {}", x),
-        None => AppState::MainMenu,
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(cli::Command::Run(options)) => options,
+        Ok(cli::Command::Render {
+            map,
+            out,
+            scale,
+            assets,
+        }) => {
+            if let Err(e) = map_image::render_to_file(&map, &out, &assets, scale) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(cli::Command::Convert { from, to }) => {
+            if let Err(e) = map::convert_file(&from, &to) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::HELP);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\nRun with --help to see the options.", e);
+            process::exit(2);
+        }
+    };
+
+    let level = match level_for(&options) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let replay = match &options.replay {
+        Some(path) => match Recording::load(path) {
+            Ok(recording) => Some(Replay {
+                recording,
+                frame: 0,
+            }),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
     };
 
     let mut app = App::build();
     app.insert_resource(WindowDescriptor {
         title: "Please Do Not Escape".to_string(),
-        width: 1920.0,
-        height: 1080.0,
+        width: options.width,
+        height: options.height,
         resizable: false,
+        mode: if options.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        },
         ..Default::default()
     })
     // .add_plugin(LogDiagnosticsPlugin::default())
     // .add_plugin(FrameTimeDiagnosticsPlugin::default())
     .insert_resource(bevy::log::LogSettings {
-        level: bevy::log::Level::DEBUG,
+        level: options.log_level,
         ..Default::default()
-    })
-    .add_plugins(DefaultPlugins);
+    });
+
+    // The fixed update stage runs once per frame instead of following the clock, so the same
+    // keys always land on the same steps.
+    let lockstep =
+        options.headless_ticks.is_some() || options.record.is_some() || options.replay.is_some();
+    app.insert_resource(Lockstep(lockstep));
+
+    match options.headless_ticks {
+        Some(ticks) => {
+            // Frames are paced like fixed steps, since some timers still read `Time`.
+            let fixed_step =
+                Balance::load_for_level(level.path.as_deref(), options.difficulty).fixed_step;
+            app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+                fixed_step,
+            )))
+            .insert_resource(Headless(true))
+            .insert_resource(ExitAfter(ticks))
+            // Nothing is drawn, but sprites and egui still need the render plugins' types, and
+            // this stands in for the GPU.
+            .insert_resource::<Box<dyn RenderResourceContext>>(Box::new(
+                HeadlessRenderResourceContext::default(),
+            ))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(LogPlugin)
+            .add_plugin(TransformPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(WindowPlugin::default())
+            .add_plugin(AssetPlugin)
+            .add_plugin(RenderPlugin::default())
+            .add_plugin(SpritePlugin);
+        }
+        None => {
+            app.add_plugins(DefaultPlugins);
+        }
+    }
 
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
 
+    // Replays reuse the recording's seed, so they play out the same way.
+    let rng = match &replay {
+        Some(replay) => GameRng::new(replay.recording.seed),
+        None => GameRng::default(),
+    };
+    if let Some(path) = &options.record {
+        app.insert_resource(Recorder {
+            path: path.clone(),
+            recording: Recording {
+                seed: rng.seed,
+                frames: vec![],
+            },
+        })
+        // Input doesn't change after `PreUpdate`, so this sees the replayed keys too.
+        .add_system_to_stage(CoreStage::PostUpdate, replay::record_input.system())
+        .add_system_to_stage(CoreStage::Last, replay::save_recording.system());
+    }
+    if let Some(replay) = replay {
+        app.insert_resource(replay).add_system_to_stage(
+            CoreStage::PreUpdate,
+            replay::replay_input.system().after(InputSystem),
+        );
+    }
+    if let (AppState::Editor, Some(path)) = (&options.state, &options.map) {
+        app.insert_resource(OpenOnStart(path.clone()));
+    }
+
     app //
         .add_plugin(EguiPlugin)
-        .add_state(initial_app_state)
+        .add_state(options.state.clone())
+        .insert_resource(level)
+        .insert_resource(options.difficulty)
+        .insert_resource(rng)
         .insert_resource(SplashScreenState::start(2.0, "menus/logo.png".into()))
         .add_system_set(
             SystemSet::on_update(AppState::Splash)
@@ -70,14 +216,28 @@ pub fn run() {
         .run();
 }
 
+/// The map file, or a generated map when there's a seed.
+fn level_for(options: &cli::Options) -> Result<Level, String> {
+    if let Some(seed) = options.seed {
+        let map = generate(seed, &options.generator)
+            .map_err(|e| format!("Could not generate a prison from seed {}: {}", seed, e))?;
+        return Ok(Level::generated(map));
+    }
+    match &options.map {
+        // Loaded now, so a bad map is reported here instead of when the game starts.
+        Some(path) => Ok(Level {
+            path: Some(path.clone()),
+            map: Some(Map::load(path)?),
+        }),
+        None => Ok(Level::default()),
+    }
+}
+
 fn check_when_splash_is_finished(
     mut state: ResMut<State<AppState>>,
     splash: Res<SplashScreenState>,
 ) {
-    match *splash {
-        SplashScreenState::Stopped => {
-            state.set(AppState::MainMenu);
-        }
-        _ => {}
+    if let SplashScreenState::Stopped = *splash {
+        state.set(AppState::MainMenu).unwrap();
     }
 }
@@ -0,0 +1,394 @@
+use crate::map::{Item, ItemInfo, Layer, Map, PathfindingMap};
+use crate::position::GridPosition;
+use crate::triggers::{Action, Condition, Selector};
+use bevy::utils::{HashMap, HashSet};
+use std::fmt;
+
+/// A problem with a map, and where it is if it's about one place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub cell: Option<GridPosition>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn at(cell: GridPosition, message: String) -> Self {
+        Self {
+            cell: Some(cell),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cell {
+            Some(cell) => write!(f, "({}, {}): {}", cell.0.x, cell.0.y, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Runs every check, sorted by position so the output is stable.
+pub fn lint(map: &Map) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    diagnostics.extend(rotations(map));
+    diagnostics.extend(overlapping(map));
+    diagnostics.extend(doors_in_walls(map));
+    diagnostics.extend(orphan_wires(map));
+    diagnostics.extend(reachability(map));
+    diagnostics.extend(trigger_targets(map));
+    diagnostics.sort_by_key(|d| d.cell.map(|c| (c.0.x, c.0.y)));
+    diagnostics
+}
+
+fn cell_of(item_info: &ItemInfo) -> GridPosition {
+    item_info.position.nearest_cell_grid_pos()
+}
+
+fn rotations(map: &Map) -> Vec<Diagnostic> {
+    map.items
+        .iter()
+        .filter(|i| i.rotation % 90.0 != 0.0)
+        .map(|i| {
+            Diagnostic::at(
+                cell_of(i),
+                format!(
+                    "{:?} is rotated {}°, not a multiple of 90°.",
+                    i.item, i.rotation
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Two items on the same layer covering the same cell, like a door on top of a wall. Background
+/// images are meant to go under other things, so they're left out.
+fn overlapping(map: &Map) -> Vec<Diagnostic> {
+    let mut seen: HashMap<(GridPosition, Layer), &ItemInfo> = HashMap::default();
+    let mut diagnostics = vec![];
+    for item_info in &map.items {
+        if let Item::Background(_) = item_info.item {
+            continue;
+        }
+        for cell in item_info.cells() {
+            let key = (cell, item_info.item.layer());
+            match seen.get(&key) {
+                Some(other) => diagnostics.push(Diagnostic::at(
+                    cell,
+                    format!("{:?} overlaps {:?}.", item_info.item, other.item),
+                )),
+                None => {
+                    seen.insert(key, item_info);
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Both ends of a door should be against a wall, or characters can walk around it.
+fn doors_in_walls(map: &Map) -> Vec<Diagnostic> {
+    let walls: HashSet<GridPosition> = map
+        .items
+        .iter()
+        .filter(|i| i.item == Item::Wall || i.item == Item::WallCorner)
+        .flat_map(|i| i.cells())
+        .collect();
+
+    let mut diagnostics = vec![];
+    for door in map.items.iter().filter(|i| i.item == Item::Door) {
+        let cells = door.cells();
+        let (first, last) = match (cells.first(), cells.last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => continue,
+        };
+        // One step past each end, along the door.
+        let step = GridPosition::new(
+            (last.0.x - first.0.x).signum(),
+            (last.0.y - first.0.y).signum(),
+        );
+        let before = &first - &step;
+        let after = &last + &step;
+        if !walls.contains(&before) || !walls.contains(&after) {
+            diagnostics.push(Diagnostic::at(
+                cell_of(door),
+                "Door isn't between two walls.".into(),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Wires on a circuit with no doors don't do anything.
+fn orphan_wires(map: &Map) -> Vec<Diagnostic> {
+    let door_circuits: HashSet<u32> = map
+        .items
+        .iter()
+        .filter(|i| i.item == Item::Door)
+        .map(|i| i.properties.circuit)
+        .collect();
+    map.items
+        .iter()
+        .filter(|i| i.item == Item::Wire && !door_circuits.contains(&i.properties.circuit))
+        .map(|i| {
+            Diagnostic::at(
+                cell_of(i),
+                format!(
+                    "Wire is on circuit {}, which has no doors.",
+                    i.properties.circuit
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Trigger conditions and actions that pick out no doors or wires never do anything.
+fn trigger_targets(map: &Map) -> Vec<Diagnostic> {
+    fn selectors<'a>(condition: &'a Condition, out: &mut Vec<(Item, &'a Selector)>) {
+        match condition {
+            Condition::DoorsOpen(s) => out.push((Item::Door, s)),
+            Condition::WiresBroken(s) => out.push((Item::Wire, s)),
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                for c in conditions {
+                    selectors(c, out);
+                }
+            }
+            Condition::After(_) | Condition::PrisonerIn(_) | Condition::PrisonerEscaping => {}
+        }
+    }
+
+    let mut diagnostics = vec![];
+    for trigger in &map.triggers {
+        let mut targets = vec![];
+        selectors(&trigger.when, &mut targets);
+        for action in &trigger.then {
+            match action {
+                Action::DamageWires(s) => targets.push((Item::Wire, s)),
+                Action::SetDoors { doors, .. } => targets.push((Item::Door, doors)),
+                Action::Notify(_) => {}
+            }
+        }
+        for (item, selector) in targets {
+            let found = map
+                .items
+                .iter()
+                .any(|i| i.item == item && selector.matches(i.properties.circuit, &cell_of(i)));
+            if !found {
+                diagnostics.push(Diagnostic {
+                    cell: None,
+                    message: format!(
+                        "Trigger {:?} looks for {:?} items with {:?}, but there aren't any.",
+                        trigger.name, item, selector
+                    ),
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// With every door open, each exit should be reachable by a prisoner, and each prisoner should be
+/// able to reach an exit.
+fn reachability(map: &Map) -> Vec<Diagnostic> {
+    let cells_of = |item: Item| -> Vec<GridPosition> {
+        map.items
+            .iter()
+            .filter(|i| i.item == item)
+            .map(cell_of)
+            .collect()
+    };
+    let prisoners = cells_of(Item::Prisoner);
+    let exits = cells_of(Item::Exit);
+    if exits.is_empty() {
+        return vec![Diagnostic {
+            cell: None,
+            message: "There are no exits.".into(),
+        }];
+    }
+
+    let mut opened = map.clone();
+    for item in opened.items.iter_mut().filter(|i| i.item == Item::Door) {
+        item.properties.door_open = true;
+    }
+    let pathfinding_map = PathfindingMap::from_map(&opened);
+
+    let mut diagnostics = vec![];
+    let mut reached: HashSet<GridPosition> = HashSet::default();
+    for prisoner in &prisoners {
+        let reachable = pathfinding_map.reachable_from(prisoner);
+        if !exits.iter().any(|e| reachable.contains(e)) {
+            diagnostics.push(Diagnostic::at(
+                *prisoner,
+                "Prisoner can't reach any exit, even with the doors open.".into(),
+            ));
+        }
+        reached.extend(reachable);
+    }
+    for exit in exits.iter().filter(|e| !reached.contains(e)) {
+        diagnostics.push(Diagnostic::at(
+            *exit,
+            "Exit can't be reached by any prisoner.".into(),
+        ));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::FlexPosition;
+    use crate::triggers::Trigger;
+    use std::path::Path;
+
+    fn at(item: Item, x: i32, y: i32, rotation: f32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), rotation)
+    }
+
+    fn on_circuit(mut item_info: ItemInfo, circuit: u32) -> ItemInfo {
+        item_info.properties.circuit = circuit;
+        item_info
+    }
+
+    fn cells(diagnostics: &[Diagnostic]) -> Vec<Option<GridPosition>> {
+        diagnostics.iter().map(|d| d.cell).collect()
+    }
+
+    /// A horizontal door at the origin, with a wall at each end.
+    fn door_between_walls() -> Vec<ItemInfo> {
+        vec![
+            at(Item::Wall, -3, 0, 0.0),
+            at(Item::Door, 0, 0, 0.0),
+            at(Item::Wall, 3, 0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn rotations() {
+        let map = Map::from_items(vec![
+            at(Item::Wall, 0, 0, 270.0),
+            at(Item::Wall, 1, 2, 45.0),
+        ]);
+        assert_eq!(
+            cells(&super::rotations(&map)),
+            vec![Some(GridPosition::new(1, 2))]
+        );
+    }
+
+    #[test]
+    fn overlapping() {
+        let map = Map::from_items(vec![
+            at(Item::GeneralTile, 0, 0, 0.0),
+            at(Item::Wall, 0, 0, 0.0),
+            at(Item::Background("bg.png".into()), 0, 0, 0.0),
+            at(Item::Background("bg.png".into()), 0, 0, 0.0),
+            at(Item::Exit, 2, 0, 0.0),
+            // Its left end covers the exit.
+            at(Item::Door, 4, 0, 0.0),
+        ]);
+        assert_eq!(
+            cells(&super::overlapping(&map)),
+            vec![Some(GridPosition::new(2, 0))]
+        );
+    }
+
+    #[test]
+    fn doors_in_walls() {
+        let map = Map::from_items(door_between_walls());
+        assert!(super::doors_in_walls(&map).is_empty());
+
+        let mut items = door_between_walls();
+        items.pop();
+        items.push(at(Item::WallCorner, 0, 3, 0.0));
+        let map = Map::from_items(items);
+        assert_eq!(
+            cells(&super::doors_in_walls(&map)),
+            vec![Some(GridPosition::zero())]
+        );
+
+        let map = Map::from_items(vec![
+            at(Item::WallCorner, 0, -3, 0.0),
+            at(Item::Door, 0, 0, 90.0),
+            at(Item::Wall, 0, 3, 90.0),
+        ]);
+        assert!(super::doors_in_walls(&map).is_empty());
+    }
+
+    #[test]
+    fn orphan_wires() {
+        let map = Map::from_items(vec![
+            on_circuit(at(Item::Door, 0, 0, 0.0), 1),
+            on_circuit(at(Item::Wire, 0, 2, 0.0), 1),
+            on_circuit(at(Item::Wire, 1, 2, 0.0), 2),
+        ]);
+        assert_eq!(
+            cells(&super::orphan_wires(&map)),
+            vec![Some(GridPosition::new(1, 2))]
+        );
+    }
+
+    #[test]
+    fn reachability() {
+        let map = Map::from_items(vec![at(Item::Prisoner, 0, 0, 0.0)]);
+        assert_eq!(cells(&super::reachability(&map)), vec![None]);
+
+        // A prisoner walled in on the left, and an exit on the right.
+        let mut items = vec![at(Item::Prisoner, 0, 0, 0.0), at(Item::Exit, 3, 0, 0.0)];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                if (x, y) != (0, 0) {
+                    items.push(at(Item::Wall, x, y, 0.0));
+                }
+            }
+        }
+        let map = Map::from_items(items.clone());
+        assert_eq!(
+            cells(&super::reachability(&map)),
+            vec![Some(GridPosition::zero()), Some(GridPosition::new(3, 0))]
+        );
+
+        // Swap the wall on the right for a closed door, which counts as open.
+        items.retain(|i| i.position != FlexPosition::Grid(GridPosition::new(1, 0)));
+        items.push(at(Item::Door, 1, 0, 90.0));
+        let map = Map::from_items(items);
+        assert!(super::reachability(&map).is_empty());
+    }
+
+    #[test]
+    fn trigger_targets() {
+        let mut map = Map::from_items(vec![
+            on_circuit(at(Item::Door, 0, 0, 0.0), 1),
+            on_circuit(at(Item::Wire, 0, 2, 0.0), 1),
+        ]);
+        map.triggers = vec![
+            Trigger {
+                name: "fine".into(),
+                when: Condition::WiresBroken(Selector::Circuit(1)),
+                then: vec![Action::SetDoors {
+                    doors: Selector::All,
+                    open: true,
+                }],
+                repeat: false,
+            },
+            Trigger {
+                name: "no wires".into(),
+                when: Condition::Any(vec![
+                    Condition::After(10.0),
+                    Condition::WiresBroken(Selector::Circuit(2)),
+                ]),
+                then: vec![Action::DamageWires(Selector::Circuit(3))],
+                repeat: false,
+            },
+        ];
+        let diagnostics = super::trigger_targets(&map);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.cell.is_none()));
+        assert!(diagnostics.iter().all(|d| d.message.contains("no wires")));
+    }
+
+    #[test]
+    fn level1_is_clean() {
+        let map = Map::load(Path::new("assets/maps/level1.json")).unwrap();
+        assert_eq!(lint(&map), vec![]);
+    }
+}
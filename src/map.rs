@@ -1,29 +1,247 @@
+use crate::ascii;
 use crate::position::{FlexPosition, GridPosition, Position};
+use crate::prefab::PrefabLink;
+use crate::tiled;
+use crate::triggers::Trigger;
 use bevy::prelude::*;
+use bevy::utils::HashSet;
+use borsh::{BorshDeserialize, BorshSerialize};
 use pathfinding::prelude::astar;
 use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 pub struct Map {
     pub items: Vec<ItemInfo>,
+    /// Scripted events for the level, see `triggers`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<Trigger>,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Map {
     pub fn new() -> Self {
-        Self { items: vec![] }
+        Self::from_items(vec![])
+    }
+
+    pub fn from_items(items: Vec<ItemInfo>) -> Self {
+        Self {
+            items,
+            triggers: vec![],
+        }
+    }
+
+    /// Picks the format from the extension: the text format in `ascii` for `.txt`, a map from
+    /// Tiled, binary for `.bin`, and JSON for anything else. Prefab placements are refreshed.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if tiled::is_tiled_path(path) {
+            return tiled::load(path);
+        }
+        let bytes = fs::read(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+        let map = if ascii::is_ascii_path(path) {
+            String::from_utf8(bytes)
+                .map_err(|e| e.to_string())
+                .and_then(|text| ascii::from_ascii(&text))
+        } else if is_binary_path(path) {
+            Self::from_binary(&bytes)
+        } else {
+            Self::from_json(&bytes)
+        };
+        let mut map = map.map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        for e in map.refresh_prefabs() {
+            warn!("{:?} uses an old copy of a prefab: {}", path, e);
+        }
+        Ok(map)
+    }
+
+    /// The file contents for `path`, in the format `load` expects for it.
+    pub fn encode(&self, path: &Path) -> Result<Vec<u8>, String> {
+        if tiled::is_tiled_path(path) {
+            Err("Tiled maps can be imported, but not written.".into())
+        } else if ascii::is_ascii_path(path) {
+            Ok(ascii::to_ascii(self).into_bytes())
+        } else if is_binary_path(path) {
+            Ok(self.to_binary())
+        } else {
+            Ok(self.to_json().into_bytes())
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let file = MapFile {
+            version: MAP_VERSION,
+            map: self,
+        };
+        serde_json::to_string_pretty(&file).unwrap()
+    }
+
+    fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        let file: MapFile<Map> = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        check_version(file.version)?;
+        Ok(file.map)
+    }
+
+    /// `BINARY_MAGIC`, `MAP_VERSION` as a little endian u32, then the map in borsh.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&MAP_VERSION.to_le_bytes());
+        bytes.extend(self.try_to_vec().unwrap());
+        bytes
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[..4] != BINARY_MAGIC {
+            return Err("Not a binary map.".into());
+        }
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        check_version(version)?;
+        if version == 1 {
+            return Self::from_binary_v1(&bytes[8..]);
+        }
+        Map::try_from_slice(&bytes[8..]).map_err(|e| e.to_string())
+    }
+
+    /// Version 1 only saved the items, without prefab links.
+    fn from_binary_v1(bytes: &[u8]) -> Result<Self, String> {
+        let items = Vec::<ItemInfoV1>::try_from_slice(bytes).map_err(|e| e.to_string())?;
+        Ok(Map::from_items(
+            items.into_iter().map(ItemInfo::from).collect(),
+        ))
+    }
+
+    /// The inclusive minimum and maximum cells covered by any item, including the whole shape of
+    /// each item. `None` when the map is empty.
+    pub fn bounds(&self) -> Option<(GridPosition, GridPosition)> {
+        let mut cells = self.items.iter().flat_map(|i| i.cells());
+        let first = cells.next()?;
+        let mut min = first;
+        let mut max = first;
+        for cell in cells {
+            min.0.x = min.0.x.min(cell.0.x);
+            min.0.y = min.0.y.min(cell.0.y);
+            max.0.x = max.0.x.max(cell.0.x);
+            max.0.y = max.0.y.max(cell.0.y);
+        }
+        Some((min, max))
+    }
+}
+
+/// Bumped when maps change in a way older versions of the game can't read. Saved at the top of
+/// JSON and binary maps.
+///
+/// 2: Added prefab links to item properties, and `triggers`.
+pub const MAP_VERSION: u32 = 2;
+
+const BINARY_MAGIC: &[u8; 4] = b"PDEM";
+
+/// The version next to the map's fields. Maps from before there was a version are version 1.
+#[derive(Serialize, Deserialize)]
+struct MapFile<M> {
+    #[serde(default = "first_version")]
+    version: u32,
+    #[serde(flatten)]
+    map: M,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version > MAP_VERSION {
+        return Err(format!(
+            "The map is version {}, but this game only reads up to version {}.",
+            version, MAP_VERSION
+        ));
     }
+    Ok(())
+}
+
+/// Converts between map formats, based on the file extensions.
+pub fn convert_file(from: &Path, to: &Path) -> Result<(), String> {
+    let bytes = Map::load(from)?.encode(to)?;
+    fs::write(to, bytes).map_err(|e| format!("Could not write {:?}: {}", to, e))
+}
+
+/// Binary maps end in `.bin`.
+pub fn is_binary_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "bin")
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 pub struct ItemInfo {
     pub item: Item,
     pub position: FlexPosition,
     pub rotation: f32,
+    #[serde(default, skip_serializing_if = "ItemProperties::is_default")]
+    pub properties: ItemProperties,
+}
+
+/// `ItemInfo` as version 1 binary maps saved it, before prefab links.
+#[derive(BorshDeserialize)]
+struct ItemInfoV1 {
+    item: Item,
+    position: FlexPosition,
+    rotation: f32,
+    door_open: bool,
+    circuit: u32,
+}
+
+impl From<ItemInfoV1> for ItemInfo {
+    fn from(old: ItemInfoV1) -> Self {
+        let mut item_info = ItemInfo::new(old.item, old.position, old.rotation);
+        item_info.properties.door_open = old.door_open;
+        item_info.properties.circuit = old.circuit;
+        item_info
+    }
+}
+
+/// Settings that only apply to some items. Left out of map files when they're all default.
+#[derive(
+    Debug, Clone, Default, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize,
+)]
+#[serde(default)]
+pub struct ItemProperties {
+    /// Doors only. Whether the door starts open.
+    pub door_open: bool,
+    /// Wires and doors. Which circuit they belong to.
+    pub circuit: u32,
+    /// Set on items placed from a prefab.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<PrefabLink>,
+}
+
+impl ItemProperties {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl ItemInfo {
+    pub fn new(item: Item, position: FlexPosition, rotation: f32) -> Self {
+        Self {
+            item,
+            position,
+            rotation,
+            properties: ItemProperties::default(),
+        }
+    }
+
+    /// Like `Item::blocks_movement`, but doors that start open don't block.
+    pub fn blocks_movement(&self) -> bool {
+        if self.item == Item::Door && self.properties.door_open {
+            return false;
+        }
+        self.item.blocks_movement()
+    }
+
     pub fn shape(&self) -> Shape {
         match self.item {
             Item::Door => {
@@ -36,6 +254,12 @@ impl ItemInfo {
             _ => Shape(vec![GridPosition::zero()]),
         }
     }
+
+    /// The cells covered by the shape of this item, at its position.
+    pub fn cells(&self) -> Vec<GridPosition> {
+        let grid_pos = self.position.nearest_cell_grid_pos();
+        self.shape().0.iter().map(|delta| &grid_pos + delta).collect()
+    }
 }
 
 pub fn angle_to_radians(a: f32) -> f32 {
@@ -48,11 +272,11 @@ pub fn angle_to_quat(a: f32) -> Quat {
 
 impl ItemInfo {
     pub fn quat(&self) -> Quat {
-        angle_to_quat(self.rotation.clone())
+        angle_to_quat(self.rotation)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 pub enum Item {
     Background(String),
     Warden,
@@ -67,6 +291,11 @@ pub enum Item {
 }
 
 impl Item {
+    /// Items that characters can't walk through. Doors start closed.
+    pub fn blocks_movement(&self) -> bool {
+        matches!(self, Item::Wall | Item::WallCorner | Item::Door)
+    }
+
     pub fn path(&self) -> PathBuf {
         match self {
             Item::Wall => "cells/wall.png".into(),
@@ -81,11 +310,54 @@ impl Item {
             Item::Background(s) => s.into(),
         }
     }
+
+    pub fn layer(&self) -> Layer {
+        match self {
+            Item::Background(_) | Item::GeneralTile | Item::CellTile => Layer::Floor,
+            Item::Wire => Layer::Wiring,
+            Item::Wall | Item::WallCorner | Item::Door | Item::Exit => Layer::Structure,
+            Item::Warden | Item::Prisoner => Layer::Actors,
+        }
+    }
+}
+
+/// Groups of items that are drawn, shown and locked together in the editor, in drawing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Floor,
+    Wiring,
+    Structure,
+    Actors,
+}
+
+impl Layer {
+    pub fn all() -> [Layer; 4] {
+        [Layer::Floor, Layer::Wiring, Layer::Structure, Layer::Actors]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Floor => "Floor",
+            Layer::Wiring => "Wiring",
+            Layer::Structure => "Structure",
+            Layer::Actors => "Actors",
+        }
+    }
+
+    /// Sprite depth, so later layers are drawn on top of earlier ones.
+    pub fn z(&self) -> f32 {
+        match self {
+            Layer::Floor => 0.0,
+            Layer::Wiring => 1.0,
+            Layer::Structure => 2.0,
+            Layer::Actors => 3.0,
+        }
+    }
 }
 
 pub struct Shape(pub Vec<GridPosition>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct PathfindingMap {
     pub walkable_cells: bevy::utils::HashMap<GridPosition, bool>,
 }
@@ -97,23 +369,69 @@ impl PathfindingMap {
         }
     }
 
+    /// Every cell inside the bounds of the map is walkable, unless an item blocks it.
+    pub fn from_map(map: &Map) -> Self {
+        let mut pathfinding_map = Self::new();
+        let (min, max) = match map.bounds() {
+            Some(b) => b,
+            None => return pathfinding_map,
+        };
+
+        for x in min.0.x..=max.0.x {
+            for y in min.0.y..=max.0.y {
+                pathfinding_map
+                    .walkable_cells
+                    .insert(GridPosition::new(x, y), true);
+            }
+        }
+
+        for item_info in &map.items {
+            if !item_info.blocks_movement() {
+                continue;
+            }
+            for cell in item_info.cells() {
+                pathfinding_map.walkable_cells.insert(cell, false);
+            }
+        }
+
+        pathfinding_map
+    }
+
     pub fn is_walkable_pos(&self, pos: &Position) -> bool {
         self.is_walkable_cell(&pos.nearest_cell())
     }
 
     /// Outside the map is not walkable.
-    fn is_walkable_cell(&self, cell: &GridPosition) -> bool {
-        *self.walkable_cells.get(&cell).unwrap_or(&false)
+    pub fn is_walkable_cell(&self, cell: &GridPosition) -> bool {
+        *self.walkable_cells.get(cell).unwrap_or(&false)
     }
 
     pub fn walkable_neighbours(&self, cell: &GridPosition) -> Vec<GridPosition> {
         GridPosition::four_directions()
             .iter()
             .map(|c| cell + c)
-            .filter(|c| self.is_walkable_cell(&c))
+            .filter(|c| self.is_walkable_cell(c))
             .collect()
     }
 
+    /// Every walkable cell that can be walked to from `start`, including `start`.
+    pub fn reachable_from(&self, start: &GridPosition) -> HashSet<GridPosition> {
+        let mut seen: HashSet<GridPosition> = HashSet::default();
+        if !self.is_walkable_cell(start) {
+            return seen;
+        }
+        let mut stack = vec![*start];
+        seen.insert(*start);
+        while let Some(cell) = stack.pop() {
+            for next in self.walkable_neighbours(&cell) {
+                if seen.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        seen
+    }
+
     pub fn find_path(
         &self,
         src: &GridPosition,
@@ -135,3 +453,193 @@ impl PathfindingMap {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(item: Item, x: i32, y: i32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), 0.0)
+    }
+
+    fn map(items: Vec<ItemInfo>) -> Map {
+        Map::from_items(items)
+    }
+
+    #[test]
+    fn bounds_of_empty_map() {
+        assert_eq!(Map::new().bounds(), None);
+    }
+
+    #[test]
+    fn bounds_of_single_item() {
+        let m = map(vec![item(Item::Wall, 3, 4)]);
+        assert_eq!(
+            m.bounds(),
+            Some((GridPosition::new(3, 4), GridPosition::new(3, 4)))
+        );
+    }
+
+    #[test]
+    fn bounds_do_not_include_origin() {
+        let m = map(vec![item(Item::Wall, 2, 3), item(Item::Wall, 5, 7)]);
+        assert_eq!(
+            m.bounds(),
+            Some((GridPosition::new(2, 3), GridPosition::new(5, 7)))
+        );
+    }
+
+    #[test]
+    fn bounds_in_negative_coordinates() {
+        let m = map(vec![item(Item::Wall, -10, -3), item(Item::Exit, -4, -8)]);
+        assert_eq!(
+            m.bounds(),
+            Some((GridPosition::new(-10, -8), GridPosition::new(-4, -3)))
+        );
+    }
+
+    #[test]
+    fn bounds_include_item_shapes() {
+        let m = map(vec![item(Item::Door, 0, 0)]);
+        assert_eq!(
+            m.bounds(),
+            Some((GridPosition::new(-2, 0), GridPosition::new(2, 0)))
+        );
+    }
+
+    #[test]
+    fn bounds_of_free_positions() {
+        let mut free = item(Item::Prisoner, 0, 0);
+        free.position = FlexPosition::Position(Position(nalgebra::Vector2::new(-1.4, 2.6)));
+        let m = map(vec![free, item(Item::Wall, 1, 1)]);
+        assert_eq!(
+            m.bounds(),
+            Some((GridPosition::new(-1, 1), GridPosition::new(1, 3)))
+        );
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut door = item(Item::Door, -2, 3);
+        door.rotation = 90.0;
+        door.properties.circuit = 4;
+        let mut free = item(Item::Prisoner, 0, 0);
+        free.position = FlexPosition::Position(Position(nalgebra::Vector2::new(0.5, -7.25)));
+        let m = map(vec![
+            door,
+            free,
+            item(Item::Background("menus/logo.png".into()), 0, 0),
+        ]);
+        let back = Map::from_binary(&m.to_binary()).unwrap();
+        assert_eq!(back.items, m.items);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut bytes = Map::new().to_binary();
+        bytes[4..8].copy_from_slice(&(MAP_VERSION + 1).to_le_bytes());
+        assert!(Map::from_binary(&bytes).is_err());
+        assert!(Map::from_binary(b"{\"items\": []}").is_err());
+
+        let json = format!("{{\"version\": {}, \"items\": []}}", MAP_VERSION + 1);
+        assert!(Map::from_json(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn json_without_a_version_is_version_1() {
+        let m = Map::from_json(br#"{"items": []}"#).unwrap();
+        assert!(m.items.is_empty());
+        let version = format!("\"version\": {}", MAP_VERSION);
+        assert!(Map::new().to_json().contains(&version));
+    }
+
+    #[test]
+    fn reads_version_1_binary_maps_from_before_prefabs() {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&1u32.to_le_bytes());
+        // One item.
+        bytes.extend(&1u32.to_le_bytes());
+        // Item::Door, at FlexPosition::Grid(-2, 3).
+        bytes.extend(&[5, 1]);
+        bytes.extend(&(-2i32).to_le_bytes());
+        bytes.extend(&3i32.to_le_bytes());
+        bytes.extend(&90f32.to_le_bytes());
+        // Open, on circuit 4.
+        bytes.push(1);
+        bytes.extend(&4u32.to_le_bytes());
+
+        let m = Map::from_binary(&bytes).unwrap();
+        let mut door = item(Item::Door, -2, 3);
+        door.rotation = 90.0;
+        door.properties.door_open = true;
+        door.properties.circuit = 4;
+        assert_eq!(m.items, vec![door]);
+    }
+
+    #[test]
+    fn version_1_binary_maps_cannot_have_prefab_links() {
+        let items = vec![item(Item::Wall, 1, 2)];
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&1u32.to_le_bytes());
+        bytes.extend(items.try_to_vec().unwrap());
+        assert!(Map::from_binary(&bytes).is_err());
+    }
+
+    #[test]
+    fn pathfinding_map_of_empty_map() {
+        let p = PathfindingMap::from_map(&Map::new());
+        assert!(p.walkable_cells.is_empty());
+    }
+
+    #[test]
+    fn pathfinding_map_includes_edges() {
+        let m = map(vec![
+            item(Item::GeneralTile, 0, 0),
+            item(Item::GeneralTile, 2, 2),
+        ]);
+        let p = PathfindingMap::from_map(&m);
+        assert_eq!(p.walkable_cells.len(), 9);
+        for x in 0..=2 {
+            for y in 0..=2 {
+                assert!(p.is_walkable_cell(&GridPosition::new(x, y)));
+            }
+        }
+        assert!(!p.is_walkable_cell(&GridPosition::new(3, 2)));
+        assert!(!p.is_walkable_cell(&GridPosition::new(-1, 0)));
+    }
+
+    #[test]
+    fn pathfinding_map_blocks_walls_and_doors() {
+        let m = map(vec![
+            item(Item::Wall, -5, -5),
+            item(Item::Door, -2, -3),
+            item(Item::Exit, -1, -1),
+        ]);
+        let p = PathfindingMap::from_map(&m);
+        assert!(!p.is_walkable_cell(&GridPosition::new(-5, -5)));
+        for x in -4..=0 {
+            assert!(!p.is_walkable_cell(&GridPosition::new(x, -3)));
+        }
+        assert!(p.is_walkable_cell(&GridPosition::new(-1, -1)));
+        assert!(p.is_walkable_cell(&GridPosition::new(-5, -1)));
+        assert!(!p.is_walkable_cell(&GridPosition::new(0, 0)));
+    }
+
+    #[test]
+    fn path_through_gap_in_wall() {
+        // A wall along x = 0 with a gap at y = 0.
+        let mut items: Vec<ItemInfo> = (-3..=3)
+            .filter(|y| *y != 0)
+            .map(|y| item(Item::Wall, 0, y))
+            .collect();
+        items.push(item(Item::GeneralTile, -3, 0));
+        items.push(item(Item::GeneralTile, 3, 0));
+        let p = PathfindingMap::from_map(&map(items));
+
+        let (path, cost) = p
+            .find_path(&GridPosition::new(-3, 3), &GridPosition::new(3, 3))
+            .unwrap();
+        assert!(path.contains(&GridPosition::new(0, 0)));
+        assert_eq!(cost, 12);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::game::GRID_SIZE;
+use crate::map::{ItemInfo, Map};
+use crate::position::Position;
+use bevy::utils::HashMap;
+use image::imageops::FilterType;
+use image::{imageops, RgbaImage};
+use std::path::{Path, PathBuf};
+
+/// Draws a map the same way the game does, without a GPU: each item's sprite at its native size,
+/// centred on `position * GRID_SIZE` and rotated anticlockwise by its rotation.
+///
+/// Rotations are rounded to the nearest 90°, which is all the map format really uses.
+///
+/// `scale` shrinks the result, e.g. 0.25 for a quarter of the size. Sprite paths are relative to
+/// `assets`.
+pub fn render_map(map: &Map, assets: &Path, scale: f32) -> Result<RgbaImage, String> {
+    if !(scale > 0.0 && scale <= 1.0) {
+        return Err(format!(
+            "The scale must be above 0 and at most 1, not {}.",
+            scale
+        ));
+    }
+
+    // Drawn in layer order, the same as their depth in the game.
+    let mut items: Vec<&ItemInfo> = map.items.iter().collect();
+    items.sort_by(|a, b| a.item.layer().z().partial_cmp(&b.item.layer().z()).unwrap());
+
+    let mut sprites: HashMap<(PathBuf, u32), RgbaImage> = HashMap::default();
+    let mut placed = vec![];
+    for item_info in items {
+        let quarter_turns = ((item_info.rotation / 90.0).round() as i32).rem_euclid(4) as u32;
+        let key = (item_info.item.path(), quarter_turns);
+        if !sprites.contains_key(&key) {
+            let sprite = load_sprite(&assets.join(&key.0), quarter_turns)?;
+            sprites.insert(key.clone(), sprite);
+        }
+        let sprite = &sprites[&key];
+
+        // World space is y up, images are y down.
+        let pos: Position = item_info.position.into();
+        let centre_x = pos.0.x * GRID_SIZE as f64;
+        let centre_y = -pos.0.y * GRID_SIZE as f64;
+        let left = (centre_x - sprite.width() as f64 / 2.0).round() as i64;
+        let top = (centre_y - sprite.height() as f64 / 2.0).round() as i64;
+        placed.push((key, left, top));
+    }
+
+    if placed.is_empty() {
+        return Err("The map is empty.".into());
+    }
+
+    let min_x = placed.iter().map(|(_, x, _)| *x).min().unwrap();
+    let min_y = placed.iter().map(|(_, _, y)| *y).min().unwrap();
+    let max_x = placed
+        .iter()
+        .map(|(k, x, _)| x + sprites[k].width() as i64)
+        .max()
+        .unwrap();
+    let max_y = placed
+        .iter()
+        .map(|(k, _, y)| y + sprites[k].height() as i64)
+        .max()
+        .unwrap();
+
+    let mut canvas = RgbaImage::new((max_x - min_x) as u32, (max_y - min_y) as u32);
+    for (key, x, y) in &placed {
+        imageops::overlay(
+            &mut canvas,
+            &sprites[key],
+            (x - min_x) as u32,
+            (y - min_y) as u32,
+        );
+    }
+
+    if scale < 1.0 {
+        let width = ((canvas.width() as f32 * scale).round() as u32).max(1);
+        let height = ((canvas.height() as f32 * scale).round() as u32).max(1);
+        canvas = imageops::resize(&canvas, width, height, FilterType::Triangle);
+    }
+    Ok(canvas)
+}
+
+/// `quarter_turns` are anticlockwise.
+fn load_sprite(path: &Path, quarter_turns: u32) -> Result<RgbaImage, String> {
+    let sprite = image::open(path)
+        .map_err(|e| format!("Could not load {:?}: {}", path, e))?
+        .to_rgba8();
+    // The image functions turn clockwise.
+    Ok(match quarter_turns {
+        1 => imageops::rotate270(&sprite),
+        2 => imageops::rotate180(&sprite),
+        3 => imageops::rotate90(&sprite),
+        _ => sprite,
+    })
+}
+
+pub fn render_to_file(
+    map_path: &Path,
+    out: &Path,
+    assets: &Path,
+    scale: f32,
+) -> Result<(), String> {
+    let map = Map::load(map_path)?;
+    let image = render_map(&map, assets, scale)?;
+    image
+        .save(out)
+        .map_err(|e| format!("Could not save {:?}: {}", out, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Item;
+    use crate::position::{FlexPosition, GridPosition};
+    use image::Rgba;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    fn at(item: Item, x: i32, y: i32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), 0.0)
+    }
+
+    /// Writes a one cell sprite of a single colour for `item`.
+    fn sprite(assets: &Path, item: Item, colour: Rgba<u8>) {
+        let path = assets.join(item.path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let size = GRID_SIZE as u32;
+        RgbaImage::from_pixel(size, size, colour)
+            .save(&path)
+            .unwrap();
+    }
+
+    #[test]
+    fn renders_a_tiny_map() {
+        let assets = tempdir().unwrap();
+        sprite(assets.path(), Item::GeneralTile, RED);
+        sprite(assets.path(), Item::Prisoner, GREEN);
+        sprite(assets.path(), Item::Wall, BLUE);
+
+        // A prisoner standing on a floor tile, with a wall to the right and one above.
+        let map = Map::from_items(vec![
+            at(Item::Prisoner, 0, 0),
+            at(Item::GeneralTile, 0, 0),
+            at(Item::Wall, 1, 0),
+            at(Item::Wall, 1, 1),
+        ]);
+        let image = render_map(&map, assets.path(), 1.0).unwrap();
+        assert_eq!(image.dimensions(), (320, 320));
+        // The prisoner is drawn over the floor, and y is up.
+        assert_eq!(*image.get_pixel(80, 240), GREEN);
+        assert_eq!(*image.get_pixel(240, 240), BLUE);
+        assert_eq!(*image.get_pixel(240, 80), BLUE);
+        assert_eq!(image.get_pixel(80, 80)[3], 0);
+
+        let small = render_map(&map, assets.path(), 0.25).unwrap();
+        assert_eq!(small.dimensions(), (80, 80));
+        assert_eq!(*small.get_pixel(60, 60), BLUE);
+    }
+
+    #[test]
+    fn rejects_bad_scales_and_missing_sprites() {
+        let assets = tempdir().unwrap();
+        let map = Map::from_items(vec![at(Item::Wall, 0, 0)]);
+        assert!(render_map(&map, assets.path(), 0.0).is_err());
+        assert!(render_map(&map, assets.path(), 2.0).is_err());
+        assert!(render_map(&map, assets.path(), 1.0).is_err());
+        assert!(render_map(&Map::new(), assets.path(), 1.0).is_err());
+    }
+}
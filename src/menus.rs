@@ -1,11 +1,10 @@
-use crate::input::exit_on_escape_key;
+use crate::balance::Difficulty;
+use crate::game::Level;
+use crate::generator::{generate, GeneratorParams};
 use crate::AppState;
-use bevy::app::{AppExit, Events};
 use bevy::prelude::*;
-use bevy_egui::egui::{FontDefinitions, FontFamily, Layout};
 use bevy_egui::{egui, EguiContext};
-
-const LOGO_ID: u64 = 0;
+use rand::{thread_rng, RngCore};
 
 pub struct MainMenu;
 
@@ -25,7 +24,7 @@ pub fn setup(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let mut camera = OrthographicCameraBundle::new_2d();
+    let camera = OrthographicCameraBundle::new_2d();
     commands.spawn_bundle(camera);
 
     let material = materials.add(asset_server.load("menus/logo.png").into());
@@ -46,12 +45,50 @@ pub fn next(
     mut state: ResMut<State<AppState>>,
     keys: Res<Input<KeyCode>>,
     mut mouse_button: ResMut<Input<MouseButton>>,
+    mut level: ResMut<Level>,
+    mut difficulty: ResMut<Difficulty>,
+    egui_context: Res<EguiContext>,
 ) {
-    for _ in keys.get_just_pressed() {
+    // Picked before starting, so `game::setup` loads the balance for it.
+    egui::Area::new("difficulty")
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -40.0])
+        .show(egui_context.ctx(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Difficulty:");
+                for choice in Difficulty::all().iter() {
+                    ui.selectable_value(&mut *difficulty, *choice, choice.name());
+                }
+            });
+        });
+    let all = Difficulty::all();
+    let index = all.iter().position(|d| d == &*difficulty).unwrap_or(1);
+    if keys.just_pressed(KeyCode::Left) && index > 0 {
+        *difficulty = all[index - 1];
+    }
+    if keys.just_pressed(KeyCode::Right) && index + 1 < all.len() {
+        *difficulty = all[index + 1];
+    }
+
+    // Endless mode: a new generated prison every time.
+    if keys.just_pressed(KeyCode::E) {
+        let seed = thread_rng().next_u64();
+        match generate(seed, &GeneratorParams::default()) {
+            Ok(map) => {
+                info!("Generated a prison from seed {}", seed);
+                *level = Level::generated(map);
+            }
+            Err(e) => warn!("Could not generate a prison from seed {}: {}", seed, e),
+        }
+    }
+
+    if keys
+        .get_just_pressed()
+        .any(|k| *k != KeyCode::Left && *k != KeyCode::Right)
+    {
         state.set(AppState::InGame).unwrap();
     }
 
-    if mouse_button.just_pressed(MouseButton::Left) {
+    if mouse_button.just_pressed(MouseButton::Left) && !egui_context.ctx().wants_pointer_input() {
         mouse_button.reset(MouseButton::Left);
         state.set(AppState::InGame).unwrap();
     }
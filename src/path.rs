@@ -15,10 +15,15 @@ impl Path {
         }
     }
 
-    fn target(&self) -> &GridPosition {
+    pub fn target(&self) -> &GridPosition {
         &self.cells[self.current]
     }
 
+    /// The current target and every cell after it.
+    pub fn remaining(&self) -> &[GridPosition] {
+        &self.cells[self.current..]
+    }
+
     fn next(&mut self) -> Option<&GridPosition> {
         let next_idx = self.current + 1;
         if self.cells.len() == next_idx {
@@ -34,7 +39,7 @@ pub fn move_along_path(mut query: Query<(&mut Velocity, &mut Path, &Position, &S
     for (mut vel, mut path, pos, speed) in query.iter_mut() {
         let target: Position = path.target().into();
         let pos: &Position = pos;
-        let diff = target - pos.clone();
+        let diff = target - *pos;
         let remaining = diff.0.magnitude_squared();
 
         if remaining < 0.1 {
@@ -1,3 +1,4 @@
+use crate::balance::Balance;
 use crate::game;
 use crate::game::{Door, Escaping, KeyboardControl, Prisoner, SpawnPoint, Warden, GRID_SIZE};
 use crate::map::{ItemInfo, PathfindingMap};
@@ -63,7 +64,7 @@ pub fn player_keyboard_movement(
 pub fn player_keyboard_action(
     mut commands: Commands,
     keys: Res<Input<KeyCode>>,
-    mut query: Query<Entity, With<KeyboardControl>>,
+    query: Query<Entity, With<KeyboardControl>>,
 ) {
     for entity in query.iter() {
         if keys.just_pressed(KeyCode::Space) {
@@ -77,6 +78,7 @@ pub fn warden_actions(
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
     mut pathfinding_map: ResMut<PathfindingMap>,
+    balance: Res<Balance>,
     mut wardens: Query<(&Position, &Direction, &mut Action), With<Warden>>,
     mut doors: Query<(Entity, &GridPosition, &Door, &ItemInfo)>,
     prisoners: Query<(Entity, &Position, &SpawnPoint), (With<Prisoner>, With<Escaping>)>,
@@ -114,8 +116,8 @@ pub fn warden_actions(
         }
 
         for (prisoner_ent, prisoner_pos, spawn_point) in prisoners.iter() {
-            let dist = warden_pos.distance_to(&prisoner_pos);
-            if dist > 1.5 {
+            let dist = warden_pos.distance_to(prisoner_pos);
+            if dist > balance.interaction_range {
                 continue;
             }
 
@@ -134,7 +136,7 @@ pub fn warden_actions(
                 continue;
             }
             let dist = warden_pos.distance_to(&wire_pos.into());
-            if dist > 1.5 {
+            if dist > balance.interaction_range {
                 continue;
             }
 
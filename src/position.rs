@@ -1,10 +1,13 @@
+use crate::balance::Balance;
 use crate::game::GRID_SIZE;
 use crate::map::PathfindingMap;
 use bevy::prelude::*;
+use borsh::{BorshDeserialize, BorshSerialize};
 use core::convert::From;
 use nalgebra::Vector2;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::ops::{Add, Deref, Div, Sub, Mul};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -33,11 +36,27 @@ impl GridPosition {
     }
 }
 
+// nalgebra doesn't implement borsh, so these write the coordinates one after another.
+impl BorshSerialize for GridPosition {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.0.x, writer)?;
+        BorshSerialize::serialize(&self.0.y, writer)
+    }
+}
+
+impl BorshDeserialize for GridPosition {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let x = <i32 as BorshDeserialize>::deserialize(buf)?;
+        let y = <i32 as BorshDeserialize>::deserialize(buf)?;
+        Ok(Self::new(x, y))
+    }
+}
+
 impl Add<&GridPosition> for &GridPosition {
     type Output = GridPosition;
 
     fn add(self, rhs: &GridPosition) -> Self::Output {
-            let mut c = self.clone();
+            let mut c = *self;
             c.0 += rhs.0;
             c
     }
@@ -47,7 +66,7 @@ impl Sub for &GridPosition {
     type Output = GridPosition;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        let mut c = self.clone();
+        let mut c = *self;
         c.0 -= rhs.0;
         c
     }
@@ -57,7 +76,7 @@ impl Add<&Direction> for &GridPosition {
     type Output = GridPosition;
 
     fn add(self, rhs: &Direction) -> Self::Output {
-        let mut pos = self.clone();
+        let mut pos = *self;
         let rhs = rhs.to_grid_pos();
         pos.0.x += rhs.0.x;
         pos.0.y += rhs.0.y;
@@ -65,7 +84,9 @@ impl Add<&Direction> for &GridPosition {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, BorshSerialize, BorshDeserialize, PartialEq,
+)]
 pub enum FlexPosition {
     Position(Position),
     Grid(GridPosition),
@@ -75,14 +96,14 @@ impl FlexPosition {
    pub fn nearest_cell_grid_pos(&self) -> GridPosition {
        match self {
            FlexPosition::Position(p) => p.nearest_cell(),
-           FlexPosition::Grid(g) => g.clone(),
+           FlexPosition::Grid(g) => *g,
        }
    }
 }
 
-impl Into<Position> for FlexPosition {
-    fn into(self) -> Position {
-        match self {
+impl From<FlexPosition> for Position {
+    fn from(pos: FlexPosition) -> Self {
+        match pos {
             FlexPosition::Position(p) => p,
             FlexPosition::Grid(g) => g.to_position(),
         }
@@ -107,23 +128,37 @@ impl Position {
 
     pub fn to_transform(&self) -> Transform {
         Transform::from_xyz(
-            self.0.x.clone() as f32 * GRID_SIZE,
-            self.0.y.clone() as f32 * GRID_SIZE,
+            self.0.x as f32 * GRID_SIZE,
+            self.0.y as f32 * GRID_SIZE,
             0.0,
         )
     }
 }
 
+impl BorshSerialize for Position {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.0.x, writer)?;
+        BorshSerialize::serialize(&self.0.y, writer)
+    }
+}
+
+impl BorshDeserialize for Position {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let x = <f64 as BorshDeserialize>::deserialize(buf)?;
+        let y = <f64 as BorshDeserialize>::deserialize(buf)?;
+        Ok(Self::new(x, y))
+    }
+}
+
 impl From<&GridPosition> for Position {
     fn from(cell: &GridPosition) -> Self {
-        Position::new(cell.clone().0.x as f64, cell.clone().0.y as f64)
+        Position::new(cell.0.x as f64, cell.0.y as f64)
     }
 }
 
 impl From<GridPosition> for Position {
     fn from(cell: GridPosition) -> Self {
-        // Second clone not required since we're consuming it.
-        Position::new(cell.clone().0.x as f64, cell.0.y as f64)
+        Position::new(cell.0.x as f64, cell.0.y as f64)
     }
 }
 
@@ -133,15 +168,15 @@ impl From<Vector2<f64>> for Position {
     }
 }
 
-impl Into<Vec2> for Position {
-    fn into(self) -> Vec2 {
-        Vec2::new(self.0.x as f32, self.0.y as f32)
+impl From<Position> for Vec2 {
+    fn from(pos: Position) -> Self {
+        Vec2::new(pos.0.x as f32, pos.0.y as f32)
     }
 }
 
-impl Into<Vec3> for Position {
-    fn into(self) -> Vec3 {
-        Vec3::new(self.0.x as f32, self.0.y as f32, 0.0)
+impl From<Position> for Vec3 {
+    fn from(pos: Position) -> Self {
+        Vec3::new(pos.0.x as f32, pos.0.y as f32, 0.0)
     }
 }
 
@@ -171,7 +206,7 @@ impl Div<f64> for Position {
     type Output = Position;
 
     fn div(self, rhs: f64) -> Self::Output {
-        Position::new(self.0.x / &rhs, self.0.y / &rhs)
+        Position::new(self.0.x / rhs, self.0.y / rhs)
     }
 }
 
@@ -179,7 +214,7 @@ impl Mul<f64> for Position {
     type Output = Position;
 
     fn mul(self, rhs: f64) -> Self::Output {
-        Position::new(self.0.x * &rhs, self.0.y * &rhs)
+        Position::new(self.0.x * rhs, self.0.y * rhs)
     }
 }
 
@@ -233,12 +268,15 @@ impl Speed {
         Self(speed)
     }
 
-    pub fn good_guy() -> Self {
-        Self::new(0.1)
+    pub fn good_guy(balance: &Balance) -> Self {
+        Self::new(balance.warden_speed)
     }
 
-    pub fn bad_guy() -> Self {
-        Self::new(0.04 + thread_rng().gen_range(0.0..0.02))
+    pub fn bad_guy(balance: &Balance, rng: &mut impl Rng) -> Self {
+        if balance.prisoner_speed_max <= balance.prisoner_speed_min {
+            return Self::new(balance.prisoner_speed_min);
+        }
+        Self::new(rng.gen_range(balance.prisoner_speed_min..balance.prisoner_speed_max))
     }
 }
 
@@ -285,6 +323,9 @@ pub fn apply_velocity(mut query: Query<(&mut Position, &Velocity)>) {
 
 pub fn sync_sprite_positions(mut query: Query<(&Position, &mut Transform), Changed<Position>>) {
     for (pos, mut transform) in query.iter_mut() {
+        // Keep the depth, which comes from the item's layer.
+        let z = transform.translation.z;
         *transform = pos.to_transform();
+        transform.translation.z = z;
     }
 }
@@ -0,0 +1,317 @@
+//! Prefabs are chunks of map saved on their own, like a cell with its door, that can be placed
+//! into maps any number of times.
+//!
+//! Each placed item keeps a `PrefabLink` back to its prefab, and maps replace those items with
+//! the prefab's current ones when they're loaded. Changes to a prefab show up everywhere it's
+//! placed, and changes to the placed items themselves are lost.
+
+use crate::map::{Item, ItemInfo, Map};
+use crate::position::{FlexPosition, GridPosition, Position};
+use borsh::{BorshDeserialize, BorshSerialize};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+
+pub const PREFABS_DIR: &str = "assets/prefabs";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prefab {
+    /// The cell that's put where the prefab is placed, and turned around.
+    pub origin: GridPosition,
+    pub items: Vec<ItemInfo>,
+}
+
+/// Where and how one placement of a prefab was put down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub struct PrefabLink {
+    pub path: String,
+    /// Tells apart placements of the same prefab in one map.
+    pub instance: u32,
+    /// Where the prefab's origin went.
+    pub origin: GridPosition,
+    /// Anticlockwise, after mirroring.
+    pub quarter_turns: u8,
+    /// Flipped left to right around the origin.
+    pub mirrored: bool,
+}
+
+impl Prefab {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let f = File::open(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+        serde_json::from_reader(f).map_err(|e| format!("Could not read {:?}: {}", path, e))
+    }
+
+    /// Items that came from other prefabs become plain items, so prefabs don't nest.
+    pub fn new(origin: GridPosition, items: &[ItemInfo]) -> Self {
+        let items = items
+            .iter()
+            .map(|i| {
+                let mut item_info = i.clone();
+                item_info.properties.prefab = None;
+                item_info
+            })
+            .collect();
+        Self { origin, items }
+    }
+
+    /// The prefab's items, moved, mirrored and turned for `link`, and linked back to it.
+    pub fn place(&self, link: &PrefabLink) -> Vec<ItemInfo> {
+        self.items
+            .iter()
+            .map(|item_info| {
+                let mut placed = item_info.clone();
+                placed.position = match item_info.position {
+                    FlexPosition::Grid(cell) => {
+                        let (x, y) = link.turn(
+                            (cell.0.x - self.origin.0.x) as f64,
+                            (cell.0.y - self.origin.0.y) as f64,
+                        );
+                        FlexPosition::Grid(GridPosition::new(
+                            link.origin.0.x + x as i32,
+                            link.origin.0.y + y as i32,
+                        ))
+                    }
+                    FlexPosition::Position(pos) => {
+                        let (x, y) = link.turn(
+                            pos.0.x - self.origin.0.x as f64,
+                            pos.0.y - self.origin.0.y as f64,
+                        );
+                        FlexPosition::Position(Position(Vector2::new(
+                            link.origin.0.x as f64 + x,
+                            link.origin.0.y as f64 + y,
+                        )))
+                    }
+                };
+                let mut rotation = item_info.rotation;
+                if link.mirrored {
+                    rotation = mirrored_rotation(&item_info.item, rotation);
+                }
+                placed.rotation = (rotation + 90.0 * link.quarter_turns as f32).rem_euclid(360.0);
+                placed.properties.prefab = Some(link.clone());
+                placed
+            })
+            .collect()
+    }
+}
+
+impl PrefabLink {
+    /// The same placement turned 90° anticlockwise around `centre`, for when its items are.
+    pub fn turned_around(&self, centre: &GridPosition) -> Self {
+        let mut link = self.clone();
+        link.origin = GridPosition::new(
+            centre.0.x - (self.origin.0.y - centre.0.y),
+            centre.0.y + (self.origin.0.x - centre.0.x),
+        );
+        link.quarter_turns = (self.quarter_turns + 1) % 4;
+        link
+    }
+
+    fn same_placement(&self, other: &PrefabLink) -> bool {
+        self.path == other.path && self.instance == other.instance
+    }
+
+    fn turn(&self, mut x: f64, mut y: f64) -> (f64, f64) {
+        if self.mirrored {
+            x = -x;
+        }
+        for _ in 0..self.quarter_turns % 4 {
+            let old_x = x;
+            x = -y;
+            y = old_x;
+        }
+        (x, y)
+    }
+}
+
+/// The rotation that looks like the item flipped left to right. Corners are drawn joining the
+/// walls up and to the left at 0°, so they need a different turn to swap left for right.
+fn mirrored_rotation(item: &Item, rotation: f32) -> f32 {
+    let flipped = match item {
+        Item::WallCorner => 270.0 - rotation,
+        _ => 180.0 - rotation,
+    };
+    flipped.rem_euclid(360.0)
+}
+
+impl Map {
+    /// One more than the highest placement number in the map.
+    pub fn next_prefab_instance(&self) -> u32 {
+        self.items
+            .iter()
+            .filter_map(|i| i.properties.prefab.as_ref())
+            .map(|l| l.instance + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Makes items plain when only some of their placement is in `items`. Otherwise moving or
+    /// turning part of a placement would put a whole extra copy of it there on the next refresh.
+    pub fn unlink_partial_placements(&self, items: &mut [ItemInfo]) {
+        let count = |items: &[ItemInfo], link: &PrefabLink| {
+            items
+                .iter()
+                .filter_map(|i| i.properties.prefab.as_ref())
+                .filter(|l| l.same_placement(link))
+                .count()
+        };
+        let partial: Vec<PrefabLink> = items
+            .iter()
+            .filter_map(|i| i.properties.prefab.clone())
+            .filter(|link| count(items, link) < count(&self.items, link))
+            .collect();
+        for item_info in items.iter_mut() {
+            let link = &mut item_info.properties.prefab;
+            if link
+                .as_ref()
+                .is_some_and(|l| partial.iter().any(|p| p.same_placement(l)))
+            {
+                *link = None;
+            }
+        }
+    }
+
+    /// Gives each placement in `items` a new number, for when they're pasted in as copies.
+    pub fn renumber_placements(&self, items: &mut [ItemInfo]) {
+        let mut next = self.next_prefab_instance();
+        let mut renumbered: Vec<(PrefabLink, u32)> = vec![];
+        for item_info in items.iter_mut() {
+            let link = match &mut item_info.properties.prefab {
+                Some(l) => l,
+                None => continue,
+            };
+            let instance = match renumbered.iter().find(|(old, _)| old.same_placement(link)) {
+                Some((_, instance)) => *instance,
+                None => {
+                    renumbered.push((link.clone(), next));
+                    next += 1;
+                    next - 1
+                }
+            };
+            link.instance = instance;
+        }
+    }
+
+    /// Replaces the items of each placement with its prefab's current items. Placements whose
+    /// prefab can't be loaded keep the items they have, and the errors are returned.
+    pub fn refresh_prefabs(&mut self) -> Vec<String> {
+        let mut links: Vec<PrefabLink> = vec![];
+        for link in self
+            .items
+            .iter()
+            .filter_map(|i| i.properties.prefab.as_ref())
+        {
+            if !links.contains(link) {
+                links.push(link.clone());
+            }
+        }
+
+        let mut errors = vec![];
+        for link in links {
+            let prefab = match Prefab::load(Path::new(&link.path)) {
+                Ok(p) => p,
+                Err(e) => {
+                    if !errors.contains(&e) {
+                        errors.push(e);
+                    }
+                    continue;
+                }
+            };
+            self.items
+                .retain(|i| i.properties.prefab.as_ref() != Some(&link));
+            self.items.extend(prefab.place(&link));
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(item: Item, x: i32, y: i32, rotation: f32) -> ItemInfo {
+        ItemInfo::new(item, FlexPosition::Grid(GridPosition::new(x, y)), rotation)
+    }
+
+    fn link(quarter_turns: u8, mirrored: bool) -> PrefabLink {
+        PrefabLink {
+            path: "assets/prefabs/test.json".into(),
+            instance: 0,
+            origin: GridPosition::new(10, 20),
+            quarter_turns,
+            mirrored,
+        }
+    }
+
+    fn cell_block() -> Prefab {
+        Prefab::new(
+            GridPosition::new(1, 1),
+            &[
+                item(Item::WallCorner, 0, 2, 180.0),
+                item(Item::Door, 1, 0, 0.0),
+                item(Item::Prisoner, 2, 1, 0.0),
+            ],
+        )
+    }
+
+    fn placed(prefab: &Prefab, link: &PrefabLink) -> Vec<(GridPosition, f32)> {
+        prefab
+            .place(link)
+            .iter()
+            .map(|i| (i.position.nearest_cell_grid_pos(), i.rotation))
+            .collect()
+    }
+
+    #[test]
+    fn places_at_the_origin() {
+        let prefab = cell_block();
+        assert_eq!(
+            placed(&prefab, &link(0, false)),
+            vec![
+                (GridPosition::new(9, 21), 180.0),
+                (GridPosition::new(10, 19), 0.0),
+                (GridPosition::new(11, 20), 0.0),
+            ]
+        );
+        assert!(prefab
+            .place(&link(0, false))
+            .iter()
+            .all(|i| i.properties.prefab == Some(link(0, false))));
+    }
+
+    #[test]
+    fn turns_anticlockwise() {
+        assert_eq!(
+            placed(&cell_block(), &link(1, false)),
+            vec![
+                (GridPosition::new(9, 19), 270.0),
+                (GridPosition::new(11, 20), 90.0),
+                (GridPosition::new(10, 21), 90.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirrors_left_to_right() {
+        assert_eq!(
+            placed(&cell_block(), &link(0, true)),
+            vec![
+                // The top left corner becomes the top right one.
+                (GridPosition::new(11, 21), 90.0),
+                (GridPosition::new(10, 19), 180.0),
+                (GridPosition::new(9, 20), 180.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn refresh_keeps_placements_with_missing_prefabs() {
+        let mut missing = link(0, false);
+        missing.path = "assets/prefabs/does-not-exist.json".into();
+        let mut map = Map::from_items(cell_block().place(&missing));
+        assert_eq!(map.next_prefab_instance(), 1);
+        let before = map.items.clone();
+        assert_eq!(map.refresh_prefabs().len(), 1);
+        assert_eq!(map.items, before);
+    }
+}
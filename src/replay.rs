@@ -0,0 +1,104 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The keys the game reads. Others aren't recorded.
+const KEYS: [KeyCode; 7] = [
+    KeyCode::W,
+    KeyCode::A,
+    KeyCode::S,
+    KeyCode::D,
+    KeyCode::Space,
+    KeyCode::E,
+    KeyCode::Escape,
+];
+
+/// The names of the keys held down in each frame. Recording and replaying run in lockstep, so
+/// each frame is one fixed step.
+///
+/// `seed` is the `GameRng` seed the recording was made with, so randomness like which wire gets
+/// damaged plays out the same way too.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(default)]
+    pub seed: u64,
+    pub frames: Vec<Vec<String>>,
+}
+
+fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let f = File::open(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+        let recording: Recording =
+            serde_json::from_reader(f).map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        let names: Vec<String> = KEYS.iter().map(|k| key_name(*k)).collect();
+        for name in recording.frames.iter().flatten() {
+            if !names.contains(name) {
+                return Err(format!("{:?} has an unknown key {:?}.", path, name));
+            }
+        }
+        Ok(recording)
+    }
+}
+
+pub struct Recorder {
+    pub path: PathBuf,
+    pub recording: Recording,
+}
+
+pub struct Replay {
+    pub recording: Recording,
+    pub frame: usize,
+}
+
+pub fn record_input(keys: Res<Input<KeyCode>>, mut recorder: ResMut<Recorder>) {
+    let held = KEYS
+        .iter()
+        .filter(|k| keys.pressed(**k))
+        .map(|k| key_name(*k))
+        .collect();
+    recorder.recording.frames.push(held);
+}
+
+pub fn save_recording(mut exits: EventReader<AppExit>, recorder: Res<Recorder>) {
+    if exits.iter().next().is_none() {
+        return;
+    }
+    let result = File::create(&recorder.path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::to_writer(f, &recorder.recording).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => info!(
+            "Saved {} frames to {:?}",
+            recorder.recording.frames.len(),
+            recorder.path
+        ),
+        Err(e) => warn!("Could not save the recording to {:?}: {}", recorder.path, e),
+    }
+}
+
+/// Runs after the real keyboard input, so the recording overrides it. Stops pressing anything
+/// once the recording runs out.
+pub fn replay_input(mut keys: ResMut<Input<KeyCode>>, mut replay: ResMut<Replay>) {
+    let held = replay
+        .recording
+        .frames
+        .get(replay.frame)
+        .cloned()
+        .unwrap_or_default();
+    for key in KEYS.iter() {
+        if held.contains(&key_name(*key)) {
+            if !keys.pressed(*key) {
+                keys.press(*key);
+            }
+        } else if keys.pressed(*key) {
+            keys.release(*key);
+        }
+    }
+    replay.frame += 1;
+}
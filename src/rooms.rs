@@ -0,0 +1,230 @@
+use crate::game::{Prisoner, SpawnPoint};
+use crate::map::PathfindingMap;
+use crate::position::{GridPosition, Position};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RoomId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKind {
+    /// A room with a prisoner spawn in it.
+    Cell,
+    /// A long thin room, at most `CORRIDOR_WIDTH` cells wide.
+    Corridor,
+    /// Any other enclosed room.
+    Yard,
+    /// Touches the edge of the map or has an exit in it.
+    Outside,
+}
+
+const CORRIDOR_WIDTH: i32 = 3;
+
+#[derive(Debug)]
+pub struct Room {
+    pub id: RoomId,
+    pub kind: RoomKind,
+    pub cells: Vec<GridPosition>,
+}
+
+/// Walkable areas of the map, separated by walls and doors.
+///
+/// Doors are always treated as a boundary, even while open, so opening a cell door doesn't merge
+/// the cell with the corridor.
+#[derive(Debug, Default)]
+pub struct Rooms {
+    pub rooms: Vec<Room>,
+    cell_to_room: HashMap<GridPosition, RoomId>,
+}
+
+impl Rooms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn detect(
+        map: &PathfindingMap,
+        door_cells: &HashSet<GridPosition>,
+        spawn_cells: &HashSet<GridPosition>,
+        exit_cells: &HashSet<GridPosition>,
+    ) -> Self {
+        let mut rooms = Rooms::new();
+
+        // Sort so room ids are stable between runs.
+        let mut walkable: Vec<&GridPosition> = map
+            .walkable_cells
+            .iter()
+            .filter(|(cell, walkable)| **walkable && !door_cells.contains(cell))
+            .map(|(cell, _)| cell)
+            .collect();
+        walkable.sort_by_key(|c| (c.0.x, c.0.y));
+
+        for start in walkable {
+            if rooms.cell_to_room.contains_key(start) {
+                continue;
+            }
+
+            let id = RoomId(rooms.rooms.len());
+            let mut cells = vec![];
+            let mut touches_edge = false;
+            let mut stack = vec![*start];
+            rooms.cell_to_room.insert(*start, id);
+            while let Some(cell) = stack.pop() {
+                cells.push(cell);
+                for delta in GridPosition::four_directions() {
+                    let neighbour = &cell + &delta;
+                    if rooms.cell_to_room.contains_key(&neighbour) {
+                        continue;
+                    }
+                    match map.walkable_cells.get(&neighbour) {
+                        // Cells that aren't in the map at all are past the bounds.
+                        None => touches_edge = true,
+                        Some(true) if !door_cells.contains(&neighbour) => {
+                            rooms.cell_to_room.insert(neighbour, id);
+                            stack.push(neighbour);
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+
+            let kind = if touches_edge || cells.iter().any(|c| exit_cells.contains(c)) {
+                RoomKind::Outside
+            } else if cells.iter().any(|c| spawn_cells.contains(c)) {
+                RoomKind::Cell
+            } else if narrowest_side(&cells) <= CORRIDOR_WIDTH {
+                RoomKind::Corridor
+            } else {
+                RoomKind::Yard
+            };
+
+            rooms.rooms.push(Room { id, kind, cells });
+        }
+
+        rooms
+    }
+
+    pub fn room_id_at(&self, cell: &GridPosition) -> Option<RoomId> {
+        self.cell_to_room.get(cell).cloned()
+    }
+
+    #[allow(dead_code)]
+    pub fn room_at(&self, cell: &GridPosition) -> Option<&Room> {
+        self.room_id_at(cell).map(|id| &self.rooms[id.0])
+    }
+
+    #[allow(dead_code)]
+    pub fn kind_at(&self, cell: &GridPosition) -> Option<RoomKind> {
+        self.room_at(cell).map(|r| r.kind)
+    }
+}
+
+/// The smallest of the width or height of the bounding box of the cells.
+fn narrowest_side(cells: &[GridPosition]) -> i32 {
+    let min_x = cells.iter().map(|c| c.0.x).min().unwrap_or(0);
+    let max_x = cells.iter().map(|c| c.0.x).max().unwrap_or(0);
+    let min_y = cells.iter().map(|c| c.0.y).min().unwrap_or(0);
+    let max_y = cells.iter().map(|c| c.0.y).max().unwrap_or(0);
+    (max_x - min_x + 1).min(max_y - min_y + 1)
+}
+
+/// Which room an entity is currently standing in. Standing in a doorway keeps the last room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InRoom(pub RoomId);
+
+/// A prisoner that isn't in the room they spawned in.
+#[derive(Debug)]
+pub struct OutsideCell;
+
+pub fn track_prisoner_rooms(
+    mut commands: Commands,
+    rooms: Res<Rooms>,
+    prisoners: Query<
+        (Entity, &Position, &SpawnPoint, Option<&InRoom>, Option<&OutsideCell>),
+        With<Prisoner>,
+    >,
+) {
+    for (entity, pos, spawn_point, in_room, outside_cell) in prisoners.iter() {
+        let room = match rooms.room_id_at(&pos.nearest_cell()) {
+            Some(r) => r,
+            None => continue,
+        };
+        if in_room.map(|r| r.0) != Some(room) {
+            commands.entity(entity).insert(InRoom(room));
+        }
+
+        let is_outside = rooms.room_id_at(&spawn_point.0) != Some(room);
+        if is_outside && outside_cell.is_none() {
+            commands.entity(entity).insert(OutsideCell);
+        } else if !is_outside && outside_cell.is_some() {
+            commands.entity(entity).remove::<OutsideCell>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `#` is a wall, `D` a door, `S` a prisoner spawn and `.` floor. The first row is y = 0.
+    const PRISON: [&str; 7] = [
+        "################",
+        "#S.#.#....#.....",
+        "#..D.D....D.....",
+        "#..#.#....#.....",
+        "####.#....#.....",
+        "####.#....#.....",
+        "################",
+    ];
+
+    fn detect(exit_cells: &HashSet<GridPosition>) -> Rooms {
+        let mut map = PathfindingMap::new();
+        let mut door_cells = HashSet::default();
+        let mut spawn_cells = HashSet::default();
+        for (y, row) in PRISON.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let cell = GridPosition::new(x as i32, y as i32);
+                map.walkable_cells.insert(cell, c == '.' || c == 'S');
+                if c == 'D' {
+                    door_cells.insert(cell);
+                }
+                if c == 'S' {
+                    spawn_cells.insert(cell);
+                }
+            }
+        }
+        Rooms::detect(&map, &door_cells, &spawn_cells, exit_cells)
+    }
+
+    #[test]
+    fn detects_each_kind_of_room() {
+        let rooms = detect(&HashSet::default());
+        assert_eq!(rooms.rooms.len(), 4);
+
+        let kind_at = |x, y| rooms.kind_at(&GridPosition::new(x, y));
+        assert_eq!(kind_at(2, 3), Some(RoomKind::Cell));
+        assert_eq!(kind_at(4, 5), Some(RoomKind::Corridor));
+        assert_eq!(kind_at(7, 4), Some(RoomKind::Yard));
+        // Open to the edge of the map on the right.
+        assert_eq!(kind_at(12, 2), Some(RoomKind::Outside));
+
+        // Doors and walls aren't in any room, even though the door joins two of them.
+        assert_eq!(kind_at(3, 2), None);
+        assert_eq!(kind_at(0, 0), None);
+        assert_eq!(
+            rooms.room_at(&GridPosition::new(1, 1)).unwrap().cells.len(),
+            6
+        );
+    }
+
+    #[test]
+    fn rooms_with_an_exit_are_outside() {
+        let exits = [GridPosition::new(9, 5)].iter().cloned().collect();
+        let rooms = detect(&exits);
+        assert_eq!(
+            rooms.kind_at(&GridPosition::new(6, 1)),
+            Some(RoomKind::Outside)
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Level scripts in Rhai, for behaviour the declarative `triggers` can't describe. A map's script
+//! sits beside it with the same name, e.g. `level1.rhai` for `level1.json`, and defines
+//! `fn on_tick()`, which `run_scripts` calls every fixed step.
+//!
+//! Scripts only see a copy of the game taken at the start of the step, and what they ask for is
+//! done after they return. There's no file access, clock or randomness, so a level plays out the
+//! same way every time, and each call can run at most `MAX_OPERATIONS`.
+//!
+//! ```text
+//! fn on_tick() {
+//!     if time() > 120.0 && !recall("lockdown", false) {
+//!         remember("lockdown", true);
+//!         for door in doors() {
+//!             if door.circuit == 2 {
+//!                 set_door(door.id, false);
+//!             }
+//!         }
+//!         notify("Block B is locked down.");
+//!     }
+//! }
+//! ```
+//!
+//! Everything scripts can call:
+//!
+//! - `time()`: seconds since the level started.
+//! - `prisoners()`: each with `id`, `x`, `y` and `escaping`.
+//! - `doors()`: each with `id`, `x`, `y`, `circuit` and `open`.
+//! - `wires()`: each with `id`, `x`, `y`, `circuit`, `damaged` and `broken`.
+//! - `walkable(x, y)`, and `path_length(from_x, from_y, to_x, to_y)`, which is -1 when there's no
+//!   path.
+//! - `set_door(id, open)`, `damage_wire(id)` and `notify(text)`.
+//! - `send_prisoner(id, x, y)`: walks there, then carries on escaping.
+//! - `remember(name, value)` and `recall(name, default)`, to keep values between steps.
+
+use crate::balance::Balance;
+use crate::game::{change_door_state, Door, Escaping, Prisoner};
+use crate::map::{ItemInfo, PathfindingMap};
+use crate::path::Path;
+use crate::position::{GridPosition, Position};
+use crate::triggers::Notifications;
+use crate::wires::{damage_wire, Broken, Circuit, Damaged, Wire};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::packages::{
+    BasicArrayPackage, BasicMapPackage, BasicMathPackage, CorePackage, MoreStringPackage, Package,
+};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// How much work one `on_tick` call can do before it's stopped, so a stuck script can't hang
+/// the game.
+const MAX_OPERATIONS: u64 = 100_000;
+
+#[derive(Debug, Clone)]
+struct ScriptPrisoner {
+    id: INT,
+    x: INT,
+    y: INT,
+    escaping: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ScriptDoor {
+    id: INT,
+    x: INT,
+    y: INT,
+    circuit: INT,
+    open: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ScriptWire {
+    id: INT,
+    x: INT,
+    y: INT,
+    circuit: INT,
+    damaged: bool,
+    broken: bool,
+}
+
+/// Ids are indexes into the lists the script was given this step.
+#[derive(Debug)]
+enum ScriptCommand {
+    SetDoor(usize, bool),
+    DamageWire(usize),
+    SendPrisoner(usize, GridPosition),
+    Notify(String),
+}
+
+/// What the functions registered with the engine read from and write to.
+struct Shared {
+    time: f64,
+    prisoners: Vec<ScriptPrisoner>,
+    doors: Vec<ScriptDoor>,
+    wires: Vec<ScriptWire>,
+    /// Only replaced when the map changes, and shared so paths can be found without holding the
+    /// lock.
+    pathfinding_map: Arc<PathfindingMap>,
+    memory: HashMap<String, Dynamic>,
+    commands: Vec<ScriptCommand>,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            time: 0.0,
+            prisoners: vec![],
+            doors: vec![],
+            wires: vec![],
+            pathfinding_map: Arc::new(PathfindingMap::new()),
+            memory: HashMap::default(),
+            commands: vec![],
+        }
+    }
+}
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Script {
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let source =
+            fs::read_to_string(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+        Self::new(path, &source)
+    }
+
+    fn new(path: &std::path::Path, source: &str) -> Result<Self, String> {
+        let shared = Arc::new(Mutex::new(Shared::new()));
+        let engine = engine(&shared);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        Ok(Self {
+            path: path.into(),
+            engine,
+            ast,
+            shared,
+        })
+    }
+
+    fn on_tick(&self) -> Result<(), Box<EvalAltResult>> {
+        self.engine
+            .call_fn(&mut Scope::new(), &self.ast, "on_tick", ())
+    }
+}
+
+/// The current level's script, if it has one. Replaced by `game::setup`.
+#[derive(Default)]
+pub struct LevelScript(pub Option<Script>);
+
+impl LevelScript {
+    /// The script beside the map at `map_path`. A script that can't be read is logged and left
+    /// out, so the level can still be played.
+    pub fn for_map(map_path: &std::path::Path) -> Self {
+        let path = path_for_map(map_path);
+        if !path.exists() {
+            return Self(None);
+        }
+        match Script::load(&path) {
+            Ok(script) => Self(Some(script)),
+            Err(e) => {
+                error!("{}", e);
+                Self(None)
+            }
+        }
+    }
+}
+
+/// A level's script sits beside its map, e.g. `level1.json` uses `level1.rhai`.
+pub fn path_for_map(map_path: &std::path::Path) -> PathBuf {
+    map_path.with_extension("rhai")
+}
+
+/// An engine with only the functions listed at the top of this file, and the parts of the
+/// standard library that can't reach outside the game.
+fn engine(shared: &Arc<Mutex<Shared>>) -> Engine {
+    let mut engine = Engine::new_raw();
+    engine.register_global_module(CorePackage::new().as_shared_module());
+    engine.register_global_module(BasicMathPackage::new().as_shared_module());
+    engine.register_global_module(BasicArrayPackage::new().as_shared_module());
+    engine.register_global_module(BasicMapPackage::new().as_shared_module());
+    engine.register_global_module(MoreStringPackage::new().as_shared_module());
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+    engine.on_print(|text| info!("{}", text));
+    engine.on_debug(|text, _, _| debug!("{}", text));
+
+    engine
+        .register_type_with_name::<ScriptPrisoner>("Prisoner")
+        .register_get("id", |p: &mut ScriptPrisoner| p.id)
+        .register_get("x", |p: &mut ScriptPrisoner| p.x)
+        .register_get("y", |p: &mut ScriptPrisoner| p.y)
+        .register_get("escaping", |p: &mut ScriptPrisoner| p.escaping);
+    engine
+        .register_type_with_name::<ScriptDoor>("Door")
+        .register_get("id", |d: &mut ScriptDoor| d.id)
+        .register_get("x", |d: &mut ScriptDoor| d.x)
+        .register_get("y", |d: &mut ScriptDoor| d.y)
+        .register_get("circuit", |d: &mut ScriptDoor| d.circuit)
+        .register_get("open", |d: &mut ScriptDoor| d.open);
+    engine
+        .register_type_with_name::<ScriptWire>("Wire")
+        .register_get("id", |w: &mut ScriptWire| w.id)
+        .register_get("x", |w: &mut ScriptWire| w.x)
+        .register_get("y", |w: &mut ScriptWire| w.y)
+        .register_get("circuit", |w: &mut ScriptWire| w.circuit)
+        .register_get("damaged", |w: &mut ScriptWire| w.damaged)
+        .register_get("broken", |w: &mut ScriptWire| w.broken);
+
+    let s = shared.clone();
+    engine.register_fn("time", move || s.lock().unwrap().time);
+    let s = shared.clone();
+    engine.register_fn("prisoners", move || -> Array {
+        let shared = s.lock().unwrap();
+        shared
+            .prisoners
+            .iter()
+            .cloned()
+            .map(Dynamic::from)
+            .collect()
+    });
+    let s = shared.clone();
+    engine.register_fn("doors", move || -> Array {
+        let shared = s.lock().unwrap();
+        shared.doors.iter().cloned().map(Dynamic::from).collect()
+    });
+    let s = shared.clone();
+    engine.register_fn("wires", move || -> Array {
+        let shared = s.lock().unwrap();
+        shared.wires.iter().cloned().map(Dynamic::from).collect()
+    });
+
+    let s = shared.clone();
+    engine.register_fn("walkable", move |x: INT, y: INT| {
+        let cell = GridPosition::new(x as i32, y as i32);
+        s.lock()
+            .unwrap()
+            .pathfinding_map
+            .walkable_cells
+            .get(&cell)
+            .cloned()
+            .unwrap_or(false)
+    });
+    let s = shared.clone();
+    engine.register_fn(
+        "path_length",
+        move |from_x: INT, from_y: INT, to_x: INT, to_y: INT| -> INT {
+            let from = GridPosition::new(from_x as i32, from_y as i32);
+            let to = GridPosition::new(to_x as i32, to_y as i32);
+            let pathfinding_map = s.lock().unwrap().pathfinding_map.clone();
+            match pathfinding_map.find_path(&from, &to) {
+                Some((steps, _)) => steps.len() as INT - 1,
+                None => -1,
+            }
+        },
+    );
+
+    let s = shared.clone();
+    engine.register_result_fn(
+        "set_door",
+        move |id: INT, open: bool| -> Result<(), Box<EvalAltResult>> {
+            let mut shared = s.lock().unwrap();
+            let idx = index(id, shared.doors.len(), "door")?;
+            shared.commands.push(ScriptCommand::SetDoor(idx, open));
+            Ok(())
+        },
+    );
+    let s = shared.clone();
+    engine.register_result_fn(
+        "damage_wire",
+        move |id: INT| -> Result<(), Box<EvalAltResult>> {
+            let mut shared = s.lock().unwrap();
+            let idx = index(id, shared.wires.len(), "wire")?;
+            shared.commands.push(ScriptCommand::DamageWire(idx));
+            Ok(())
+        },
+    );
+    let s = shared.clone();
+    engine.register_result_fn(
+        "send_prisoner",
+        move |id: INT, x: INT, y: INT| -> Result<(), Box<EvalAltResult>> {
+            let mut shared = s.lock().unwrap();
+            let idx = index(id, shared.prisoners.len(), "prisoner")?;
+            let cell = GridPosition::new(x as i32, y as i32);
+            shared.commands.push(ScriptCommand::SendPrisoner(idx, cell));
+            Ok(())
+        },
+    );
+    let s = shared.clone();
+    engine.register_fn("notify", move |text: &str| {
+        let mut shared = s.lock().unwrap();
+        shared.commands.push(ScriptCommand::Notify(text.into()));
+    });
+
+    let s = shared.clone();
+    engine.register_fn("remember", move |name: &str, value: Dynamic| {
+        s.lock().unwrap().memory.insert(name.into(), value);
+    });
+    let s = shared.clone();
+    engine.register_fn("recall", move |name: &str, default: Dynamic| {
+        s.lock()
+            .unwrap()
+            .memory
+            .get(name)
+            .cloned()
+            .unwrap_or(default)
+    });
+
+    engine
+}
+
+fn index(id: INT, len: usize, kind: &str) -> Result<usize, Box<EvalAltResult>> {
+    if id < 0 || id as usize >= len {
+        return Err(format!("There's no {} with id {}.", kind, id).into());
+    }
+    Ok(id as usize)
+}
+
+pub fn run_scripts(
+    mut commands: Commands,
+    balance: Res<Balance>,
+    mut level_script: ResMut<LevelScript>,
+    mut pathfinding_map: ResMut<PathfindingMap>,
+    mut notifications: ResMut<Notifications>,
+    prisoners: Query<(Entity, &Position, Option<&Escaping>), With<Prisoner>>,
+    doors: Query<(Entity, &Door, &Circuit, &GridPosition, &ItemInfo)>,
+    wires: Query<
+        (
+            Entity,
+            &Circuit,
+            &GridPosition,
+            Option<&Damaged>,
+            Option<&Broken>,
+        ),
+        With<Wire>,
+    >,
+) {
+    let script = match &level_script.0 {
+        Some(s) => s,
+        None => return,
+    };
+
+    // Sorted so ids don't depend on query order.
+    let mut prisoners: Vec<_> = prisoners.iter().collect();
+    prisoners.sort_by_key(|(entity, ..)| entity.id());
+    let mut doors: Vec<_> = doors.iter().collect();
+    doors.sort_by_key(|(entity, ..)| entity.id());
+    let mut wires: Vec<_> = wires.iter().collect();
+    wires.sort_by_key(|(entity, ..)| entity.id());
+
+    {
+        let mut shared = script.shared.lock().unwrap();
+        // Counted in fixed steps rather than read from `Time`, so replays run the same way.
+        shared.time += balance.fixed_step;
+        shared.prisoners = prisoners
+            .iter()
+            .enumerate()
+            .map(|(id, (_, pos, escaping))| {
+                let cell = pos.nearest_cell();
+                ScriptPrisoner {
+                    id: id as INT,
+                    x: cell.0.x as INT,
+                    y: cell.0.y as INT,
+                    escaping: escaping.is_some(),
+                }
+            })
+            .collect();
+        shared.doors = doors
+            .iter()
+            .enumerate()
+            .map(|(id, (_, door, circuit, cell, _))| ScriptDoor {
+                id: id as INT,
+                x: cell.0.x as INT,
+                y: cell.0.y as INT,
+                circuit: circuit.0 as INT,
+                open: door.0,
+            })
+            .collect();
+        shared.wires = wires
+            .iter()
+            .enumerate()
+            .map(|(id, (_, circuit, cell, damaged, broken))| ScriptWire {
+                id: id as INT,
+                x: cell.0.x as INT,
+                y: cell.0.y as INT,
+                circuit: circuit.0 as INT,
+                damaged: damaged.is_some(),
+                broken: broken.is_some(),
+            })
+            .collect();
+        // A new script starts with an empty map. Changes this system makes, like `set_door`,
+        // don't show up in `is_changed` on its next run, so they're copied below instead.
+        if pathfinding_map.is_changed() || level_script.is_changed() {
+            shared.pathfinding_map = Arc::new(pathfinding_map.clone());
+        }
+    }
+
+    if let Err(e) = script.on_tick() {
+        error!("{:?} stopped: {}", script.path, e);
+        level_script.0 = None;
+        return;
+    }
+
+    let queued = std::mem::take(&mut script.shared.lock().unwrap().commands);
+    let mut doors_changed = false;
+    for command in queued {
+        match command {
+            ScriptCommand::SetDoor(idx, open) => {
+                let (entity, door, _, cell, item_info) = doors[idx];
+                if door.0 != open {
+                    change_door_state(
+                        &mut commands,
+                        &mut pathfinding_map,
+                        entity,
+                        cell,
+                        item_info,
+                        open,
+                    );
+                    doors_changed = true;
+                }
+            }
+            ScriptCommand::DamageWire(idx) => {
+                let (entity, _, _, damaged, broken) = wires[idx];
+                if damaged.is_none() && broken.is_none() {
+                    damage_wire(&mut commands, entity, &balance);
+                }
+            }
+            ScriptCommand::SendPrisoner(idx, to) => {
+                let (entity, pos, _) = prisoners[idx];
+                match pathfinding_map.find_path(&pos.nearest_cell(), &to) {
+                    Some((steps, _)) => {
+                        commands.entity(entity).insert(Path::new(&steps));
+                    }
+                    None => warn!("No path for prisoner {} to {:?}.", idx, to),
+                }
+            }
+            ScriptCommand::Notify(text) => notifications.push(&text),
+        }
+    }
+    if doors_changed {
+        script.shared.lock().unwrap().pathfinding_map = Arc::new(pathfinding_map.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(source: &str) -> Script {
+        Script::new(std::path::Path::new("test.rhai"), source).unwrap()
+    }
+
+    fn door(id: INT, open: bool) -> ScriptDoor {
+        ScriptDoor {
+            id,
+            x: 0,
+            y: 0,
+            circuit: 1,
+            open,
+        }
+    }
+
+    #[test]
+    fn queues_commands() {
+        let script = script(
+            r#"
+            fn on_tick() {
+                for door in doors() {
+                    set_door(door.id, !door.open);
+                }
+                notify("Doors swapped.");
+            }
+            "#,
+        );
+        script.shared.lock().unwrap().doors = vec![door(0, false), door(1, true)];
+        script.on_tick().unwrap();
+        let commands = &script.shared.lock().unwrap().commands;
+        assert_eq!(commands.len(), 3);
+        assert!(matches!(commands[0], ScriptCommand::SetDoor(0, true)));
+        assert!(matches!(commands[1], ScriptCommand::SetDoor(1, false)));
+    }
+
+    #[test]
+    fn rejects_unknown_ids() {
+        let script = script("fn on_tick() { damage_wire(3); }");
+        assert!(script.on_tick().is_err());
+        assert!(script.shared.lock().unwrap().commands.is_empty());
+    }
+
+    #[test]
+    fn remembers_between_steps() {
+        let script = script(
+            r#"
+            fn on_tick() {
+                let steps = recall("steps", 0);
+                remember("steps", steps + 1);
+                if steps == 2 {
+                    notify("Third step.");
+                }
+            }
+            "#,
+        );
+        for _ in 0..4 {
+            script.on_tick().unwrap();
+        }
+        assert_eq!(script.shared.lock().unwrap().commands.len(), 1);
+    }
+
+    #[test]
+    fn stops_scripts_that_never_finish() {
+        let script = script("fn on_tick() { loop {} }");
+        assert!(script.on_tick().is_err());
+    }
+
+    #[test]
+    fn has_no_clock() {
+        assert!(script("fn on_tick() { timestamp(); }").on_tick().is_err());
+    }
+}
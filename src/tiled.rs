@@ -0,0 +1,803 @@
+//! Imports maps made in Tiled (https://www.mapeditor.org), saved as TMX or as Tiled JSON (`.tmj`).
+//!
+//! Every tile in a tile layer becomes one item in its cell. Objects become items at their centre,
+//! so spawns don't have to be lined up with the grid. Tiled's top left tile is cell (0, 0), and
+//! rows go down from there.
+//!
+//! Which item a tile stands for comes from, in order:
+//! - its class (called type before Tiled 1.9), like `Wall` or `Prisoner`,
+//! - an `item` property with the same names,
+//! - the file name of its image, when it's one of the game's sprites, like `wall.png`.
+//!
+//! Objects are looked up the same way, falling back to their tile for tile objects. Objects with
+//! none of these, like notes, are skipped. `circuit` and `door_open` properties on tiles or
+//! objects set the item's properties.
+//!
+//! Tiled's rotate buttons set flip flags, which become rotations. Tiles that are only mirrored
+//! can't be shown in the game, so they're an error. Image layers, compressed layer data and
+//! infinite maps aren't supported.
+
+use crate::map::{Item, ItemInfo, ItemProperties, Map};
+use crate::position::{FlexPosition, GridPosition, Position};
+use bevy::utils::HashMap;
+use nalgebra::Vector2;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// Only used by hexagonal maps, but cleared anyway.
+const ROTATED_HEXAGONAL_120: u32 = 0x1000_0000;
+const FLAGS: u32 =
+    FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL_120;
+
+/// Items that can be placed from Tiled. Backgrounds aren't on the grid, so they're left out.
+const ITEMS: [Item; 9] = [
+    Item::Warden,
+    Item::Prisoner,
+    Item::Wall,
+    Item::WallCorner,
+    Item::Door,
+    Item::Exit,
+    Item::Wire,
+    Item::GeneralTile,
+    Item::CellTile,
+];
+
+/// `.tmx` and `.tmj` files.
+pub fn is_tiled_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "tmx" || e == "tmj")
+}
+
+pub fn load(path: &Path) -> Result<Map, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+    // External tilesets are relative to the map.
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let tiled = if path.extension().is_some_and(|e| e == "tmx") {
+        read_tmx(&text, dir)
+    } else {
+        read_json(&text, dir)
+    };
+    tiled
+        .and_then(|t| t.to_map())
+        .map_err(|e| format!("Could not import {:?}: {}", path, e))
+}
+
+/// The parts of a Tiled map that are used, the same for both file formats.
+struct TiledMap {
+    tile_width: f64,
+    tile_height: f64,
+    tilesets: Vec<Tileset>,
+    layers: Vec<TiledLayer>,
+}
+
+struct Tileset {
+    name: String,
+    first_gid: u32,
+    /// By local tile id. Tiles with nothing set aren't listed by Tiled.
+    tiles: HashMap<u32, Tile>,
+}
+
+#[derive(Default)]
+struct Tile {
+    class: Option<String>,
+    image: Option<String>,
+    properties: HashMap<String, String>,
+}
+
+enum TiledLayer {
+    Tiles {
+        name: String,
+        width: u32,
+        gids: Vec<u32>,
+    },
+    Objects(Vec<Object>),
+}
+
+#[derive(Default)]
+struct Object {
+    name: String,
+    class: Option<String>,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    /// Degrees clockwise.
+    rotation: f64,
+    gid: Option<u32>,
+    properties: HashMap<String, String>,
+}
+
+impl TiledMap {
+    fn to_map(&self) -> Result<Map, String> {
+        let mut items = vec![];
+        for layer in &self.layers {
+            match layer {
+                TiledLayer::Tiles { name, width, gids } => {
+                    if *width == 0 {
+                        continue;
+                    }
+                    for (idx, gid) in gids.iter().enumerate() {
+                        if *gid == 0 {
+                            continue;
+                        }
+                        let column = (idx as u32 % width) as i32;
+                        let row = (idx as u32 / width) as i32;
+                        let at = || format!("Layer {:?}, tile ({}, {})", name, column, row);
+                        let (item, rotation, properties) = self
+                            .tile_item(*gid)
+                            .map_err(|e| format!("{}: {}", at(), e))?;
+                        let cell = GridPosition::new(column, -row);
+                        let mut item_info = ItemInfo::new(item, FlexPosition::Grid(cell), rotation);
+                        item_info.properties =
+                            item_properties(&properties).map_err(|e| format!("{}: {}", at(), e))?;
+                        items.push(item_info);
+                    }
+                }
+                TiledLayer::Objects(objects) => {
+                    for object in objects {
+                        if let Some(item_info) = self
+                            .object_item(object)
+                            .map_err(|e| format!("Object {:?}: {}", object.name, e))?
+                        {
+                            items.push(item_info);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Map::from_items(items))
+    }
+
+    fn tile(&self, gid: u32) -> Result<(&Tileset, Option<&Tile>), String> {
+        let tileset = self
+            .tilesets
+            .iter()
+            .filter(|t| t.first_gid <= gid)
+            .max_by_key(|t| t.first_gid)
+            .ok_or_else(|| format!("no tileset has tile {}", gid))?;
+        Ok((tileset, tileset.tiles.get(&(gid - tileset.first_gid))))
+    }
+
+    /// The item, rotation and properties for a tile with its flip flags.
+    fn tile_item(&self, gid: u32) -> Result<(Item, f32, HashMap<String, String>), String> {
+        let rotation = flags_to_rotation(gid & FLAGS)?;
+        let gid = gid & !FLAGS;
+        let (tileset, tile) = self.tile(gid)?;
+        let item = tile.and_then(|t| t.item()).ok_or_else(|| {
+            format!(
+                "tile {} in tileset {:?} isn't an item. Set its class to an item name, like Wall.",
+                gid - tileset.first_gid,
+                tileset.name
+            )
+        })??;
+        let properties = tile.map(|t| t.properties.clone()).unwrap_or_default();
+        Ok((item, rotation, properties))
+    }
+
+    fn object_item(&self, object: &Object) -> Result<Option<ItemInfo>, String> {
+        let (item, mut rotation, mut properties) = match (object.item(), object.gid) {
+            (Some(item), gid) => {
+                let rotation = match gid {
+                    Some(gid) => flags_to_rotation(gid & FLAGS)?,
+                    None => 0.0,
+                };
+                (item?, rotation, HashMap::default())
+            }
+            (None, Some(gid)) => self.tile_item(gid)?,
+            (None, None) => return Ok(None),
+        };
+        properties.extend(object.properties.clone());
+        rotation = (rotation - object.rotation as f32).rem_euclid(360.0);
+
+        // Tile objects hang up from their bottom left corner, other objects down from their top
+        // left, and both rotate around it.
+        let (dx, dy) = if object.gid.is_some() {
+            (object.width / 2.0, -object.height / 2.0)
+        } else {
+            (object.width / 2.0, object.height / 2.0)
+        };
+        let (sin, cos) = object.rotation.to_radians().sin_cos();
+        let centre_x = object.x + dx * cos - dy * sin;
+        let centre_y = object.y + dx * sin + dy * cos;
+
+        // The middle of tile (0, 0) is cell (0, 0).
+        let x = centre_x / self.tile_width - 0.5;
+        let y = -(centre_y / self.tile_height - 0.5);
+        let position = if is_whole(x) && is_whole(y) {
+            FlexPosition::Grid(GridPosition::new(x.round() as i32, y.round() as i32))
+        } else {
+            FlexPosition::Position(Position(Vector2::new(x, y)))
+        };
+
+        let mut item_info = ItemInfo::new(item, position, rotation);
+        item_info.properties = item_properties(&properties)?;
+        Ok(Some(item_info))
+    }
+}
+
+impl Tile {
+    fn item(&self) -> Option<Result<Item, String>> {
+        if let Some(name) = self.class.as_ref().or_else(|| self.properties.get("item")) {
+            return Some(item_named(name));
+        }
+        self.image.as_ref().and_then(|i| item_for_image(i)).map(Ok)
+    }
+}
+
+impl Object {
+    fn item(&self) -> Option<Result<Item, String>> {
+        self.class
+            .as_ref()
+            .or_else(|| self.properties.get("item"))
+            .map(|name| item_named(name))
+    }
+}
+
+fn is_whole(v: f64) -> bool {
+    (v - v.round()).abs() < 1e-6
+}
+
+/// Rotating in Tiled flips a tile diagonally and then along one axis. Tiled rotates clockwise on
+/// screen, and rotations in the game are anticlockwise.
+fn flags_to_rotation(flags: u32) -> Result<f32, String> {
+    match flags & !ROTATED_HEXAGONAL_120 {
+        0 => Ok(0.0),
+        f if f == FLIPPED_DIAGONALLY | FLIPPED_VERTICALLY => Ok(90.0),
+        f if f == FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY => Ok(180.0),
+        f if f == FLIPPED_DIAGONALLY | FLIPPED_HORIZONTALLY => Ok(270.0),
+        _ => Err("it's mirrored, which the game can't show. Rotate it instead.".into()),
+    }
+}
+
+fn item_named(name: &str) -> Result<Item, String> {
+    let wanted = name.replace([' ', '_', '-'], "");
+    ITEMS
+        .iter()
+        .find(|item| format!("{:?}", item).eq_ignore_ascii_case(&wanted))
+        .cloned()
+        .ok_or_else(|| format!("{:?} isn't an item.", name))
+}
+
+fn item_for_image(image: &str) -> Option<Item> {
+    let file_name = Path::new(image).file_name()?;
+    ITEMS
+        .iter()
+        .find(|item| item.path().file_name() == Some(file_name))
+        .cloned()
+}
+
+fn item_properties(properties: &HashMap<String, String>) -> Result<ItemProperties, String> {
+    let mut item_properties = ItemProperties::default();
+    if let Some(circuit) = properties.get("circuit") {
+        item_properties.circuit = circuit
+            .parse()
+            .map_err(|_| format!("circuit should be a whole number, not {:?}.", circuit))?;
+    }
+    if let Some(door_open) = properties.get("door_open") {
+        item_properties.door_open = door_open
+            .parse()
+            .map_err(|_| format!("door_open should be true or false, not {:?}.", door_open))?;
+    }
+    Ok(item_properties)
+}
+
+/// Layer data is either CSV or base64 encoded little endian numbers.
+fn decode_gids(
+    encoding: Option<&str>,
+    compression: Option<&str>,
+    data: &str,
+) -> Result<Vec<u32>, String> {
+    if let Some(compression) = compression.filter(|c| !c.is_empty()) {
+        return Err(format!(
+            "{} compressed layers aren't supported. Save them as CSV or uncompressed Base64.",
+            compression
+        ));
+    }
+    match encoding {
+        Some("base64") => {
+            let bytes = base64::decode(data.trim()).map_err(|e| e.to_string())?;
+            if bytes.len() % 4 != 0 {
+                return Err("Layer data isn't a whole number of tiles.".into());
+            }
+            Ok(bytes
+                .chunks(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        Some("csv") => data
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().map_err(|_| format!("Bad tile {:?}.", s)))
+            .collect(),
+        _ => Err(format!("Unknown layer encoding {:?}.", encoding)),
+    }
+}
+
+// Tiled JSON.
+
+#[derive(Deserialize)]
+struct JsonMap {
+    tilewidth: f64,
+    tileheight: f64,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<JsonLayer>,
+    tilesets: Vec<JsonTileset>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    width: u32,
+    data: Option<serde_json::Value>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    /// Group layers.
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    class: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    image: Option<String>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    class: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    x: f64,
+    y: f64,
+    #[serde(default)]
+    width: f64,
+    #[serde(default)]
+    height: f64,
+    #[serde(default)]
+    rotation: f64,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+fn json_properties(properties: &[JsonProperty]) -> HashMap<String, String> {
+    properties
+        .iter()
+        .map(|p| {
+            let value = match &p.value {
+                serde_json::Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            (p.name.clone(), value)
+        })
+        .collect()
+}
+
+/// Tiled writes an empty string for no class.
+fn non_empty(s: &Option<String>) -> Option<String> {
+    s.clone().filter(|s| !s.is_empty())
+}
+
+fn read_json(text: &str, dir: &Path) -> Result<TiledMap, String> {
+    let map: JsonMap = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if map.infinite {
+        return Err("Infinite maps aren't supported.".into());
+    }
+    let mut tilesets = vec![];
+    for tileset in &map.tilesets {
+        tilesets.push(match &tileset.source {
+            Some(source) => load_tileset(&dir.join(source), tileset.firstgid)?,
+            None => json_tileset(tileset, tileset.firstgid),
+        });
+    }
+    let mut layers = vec![];
+    json_layers(&map.layers, &mut layers)?;
+    Ok(TiledMap {
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        tilesets,
+        layers,
+    })
+}
+
+fn json_tileset(tileset: &JsonTileset, first_gid: u32) -> Tileset {
+    Tileset {
+        name: tileset.name.clone(),
+        first_gid,
+        tiles: tileset
+            .tiles
+            .iter()
+            .map(|t| {
+                let tile = Tile {
+                    class: non_empty(&t.class).or_else(|| non_empty(&t.kind)),
+                    image: t.image.clone(),
+                    properties: json_properties(&t.properties),
+                };
+                (t.id, tile)
+            })
+            .collect(),
+    }
+}
+
+fn json_layers(from: &[JsonLayer], layers: &mut Vec<TiledLayer>) -> Result<(), String> {
+    for layer in from {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let gids = match &layer.data {
+                    Some(serde_json::Value::Array(data)) => data
+                        .iter()
+                        .map(|v| v.as_u64().map(|v| v as u32))
+                        .collect::<Option<Vec<u32>>>()
+                        .ok_or_else(|| format!("Layer {:?} has bad tiles.", layer.name))?,
+                    Some(serde_json::Value::String(data)) => decode_gids(
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                        data,
+                    )?,
+                    _ => return Err(format!("Layer {:?} has no tiles.", layer.name)),
+                };
+                layers.push(TiledLayer::Tiles {
+                    name: layer.name.clone(),
+                    width: layer.width,
+                    gids,
+                });
+            }
+            "objectgroup" => layers.push(TiledLayer::Objects(
+                layer
+                    .objects
+                    .iter()
+                    .map(|o| Object {
+                        name: o.name.clone(),
+                        class: non_empty(&o.class).or_else(|| non_empty(&o.kind)),
+                        x: o.x,
+                        y: o.y,
+                        width: o.width,
+                        height: o.height,
+                        rotation: o.rotation,
+                        gid: o.gid,
+                        properties: json_properties(&o.properties),
+                    })
+                    .collect(),
+            )),
+            "group" => json_layers(&layer.layers, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// External tilesets, in either format.
+fn load_tileset(path: &Path, first_gid: u32) -> Result<Tileset, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+    let tileset = if path.extension().is_some_and(|e| e == "tsx") {
+        parse_xml(&text).and_then(|root| tmx_tileset(&root, first_gid))
+    } else {
+        serde_json::from_str(&text)
+            .map(|t| json_tileset(&t, first_gid))
+            .map_err(|e| e.to_string())
+    };
+    tileset.map_err(|e| format!("Could not read {:?}: {}", path, e))
+}
+
+// TMX, which is XML.
+
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|s| s.as_str())
+    }
+
+    fn number<T: std::str::FromStr + Default>(&self, name: &str) -> Result<T, String> {
+        match self.attr(name) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| format!("<{} {}> isn't a number: {:?}", self.name, name, v)),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn class(&self) -> Option<String> {
+        self.attr("class")
+            .or_else(|| self.attr("type"))
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+    }
+
+    fn properties(&self) -> HashMap<String, String> {
+        self.children("properties")
+            .flat_map(|p| p.children("property"))
+            .filter_map(|p| {
+                let value = p.attr("value").unwrap_or(&p.text);
+                Some((p.attr("name")?.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Element, String> {
+    use xml::reader::{EventReader, XmlEvent};
+
+    let mut stack: Vec<Element> = vec![];
+    for event in EventReader::from_str(text) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => stack.push(Element {
+                name: name.local_name,
+                attributes: attributes
+                    .into_iter()
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                ..Default::default()
+            }),
+            XmlEvent::EndElement { .. } => {
+                let element = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text += &s;
+                }
+            }
+            _ => {}
+        }
+    }
+    Err("The XML ended early.".into())
+}
+
+fn read_tmx(text: &str, dir: &Path) -> Result<TiledMap, String> {
+    let root = parse_xml(text)?;
+    if root.name != "map" {
+        return Err(format!("Expected a <map>, not <{}>.", root.name));
+    }
+    if root.attr("infinite") == Some("1") {
+        return Err("Infinite maps aren't supported.".into());
+    }
+    let mut tilesets = vec![];
+    for tileset in root.children("tileset") {
+        let first_gid = tileset.number("firstgid")?;
+        tilesets.push(match tileset.attr("source") {
+            Some(source) => load_tileset(&dir.join(source), first_gid)?,
+            None => tmx_tileset(tileset, first_gid)?,
+        });
+    }
+    let mut layers = vec![];
+    tmx_layers(&root, &mut layers)?;
+    Ok(TiledMap {
+        tile_width: root.number("tilewidth")?,
+        tile_height: root.number("tileheight")?,
+        tilesets,
+        layers,
+    })
+}
+
+fn tmx_tileset(tileset: &Element, first_gid: u32) -> Result<Tileset, String> {
+    let mut tiles = HashMap::default();
+    for tile in tileset.children("tile") {
+        tiles.insert(
+            tile.number("id")?,
+            Tile {
+                class: tile.class(),
+                image: tile
+                    .children("image")
+                    .next()
+                    .and_then(|i| i.attr("source"))
+                    .map(|s| s.to_string()),
+                properties: tile.properties(),
+            },
+        );
+    }
+    Ok(Tileset {
+        name: tileset.attr("name").unwrap_or_default().to_string(),
+        first_gid,
+        tiles,
+    })
+}
+
+fn tmx_layers(parent: &Element, layers: &mut Vec<TiledLayer>) -> Result<(), String> {
+    for layer in &parent.children {
+        let name = layer.attr("name").unwrap_or_default().to_string();
+        match layer.name.as_str() {
+            "layer" => {
+                let data = layer
+                    .children("data")
+                    .next()
+                    .ok_or_else(|| format!("Layer {:?} has no tiles.", name))?;
+                let gids = match data.attr("encoding") {
+                    // Without an encoding, each tile is its own element.
+                    None => data
+                        .children("tile")
+                        .map(|t| t.number("gid"))
+                        .collect::<Result<Vec<u32>, String>>()?,
+                    encoding => decode_gids(encoding, data.attr("compression"), &data.text)?,
+                };
+                layers.push(TiledLayer::Tiles {
+                    name,
+                    width: layer.number("width")?,
+                    gids,
+                });
+            }
+            "objectgroup" => {
+                let mut objects = vec![];
+                for object in layer.children("object") {
+                    objects.push(Object {
+                        name: object.attr("name").unwrap_or_default().to_string(),
+                        class: object.class(),
+                        x: object.number("x")?,
+                        y: object.number("y")?,
+                        width: object.number("width")?,
+                        height: object.number("height")?,
+                        rotation: object.number("rotation")?,
+                        gid: object
+                            .attr("gid")
+                            .map(|_| object.number("gid"))
+                            .transpose()?,
+                        properties: object.properties(),
+                    });
+                }
+                layers.push(TiledLayer::Objects(objects));
+            }
+            "group" => tmx_layers(layer, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(x: i32, y: i32) -> FlexPosition {
+        FlexPosition::Grid(GridPosition::new(x, y))
+    }
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.9" orientation="orthogonal" width="3" height="2" tilewidth="32" tileheight="32" infinite="0">
+ <tileset firstgid="1" name="prison">
+  <tile id="0" class="Wall"/>
+  <tile id="1"><image source="../cells/wall-corner.png"/></tile>
+  <tile id="2" type="Door">
+   <properties><property name="circuit" type="int" value="3"/></properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="Walls" width="3" height="2">
+  <data encoding="csv">
+2,1,3221225474,
+0,2684354561,3
+</data>
+ </layer>
+ <objectgroup id="2" name="Spawns">
+  <object id="1" name="first" type="Prisoner" x="32" y="32" width="32" height="32"/>
+  <object id="2" name="loose" class="Warden" x="8" y="8"/>
+  <object id="3" name="note" x="0" y="0" width="10" height="10"/>
+ </objectgroup>
+</map>
+"#;
+
+    #[test]
+    fn imports_tmx() {
+        let tiled = read_tmx(TMX, Path::new("")).unwrap();
+        let map = tiled.to_map().unwrap();
+        assert_eq!(map.items.len(), 7);
+
+        let mut door = ItemInfo::new(Item::Door, cell(2, -1), 0.0);
+        door.properties.circuit = 3;
+        let expected = vec![
+            ItemInfo::new(Item::WallCorner, cell(0, 0), 0.0),
+            ItemInfo::new(Item::Wall, cell(1, 0), 0.0),
+            // Rotated 180° in Tiled.
+            ItemInfo::new(Item::WallCorner, cell(2, 0), 180.0),
+            // Rotated 90° clockwise in Tiled.
+            ItemInfo::new(Item::Wall, cell(1, -1), 270.0),
+            door,
+            ItemInfo::new(Item::Prisoner, cell(1, -1), 0.0),
+            ItemInfo::new(
+                Item::Warden,
+                FlexPosition::Position(Position(Vector2::new(-0.25, 0.25))),
+                0.0,
+            ),
+        ];
+        assert_eq!(map.items, expected);
+    }
+
+    #[test]
+    fn imports_json() {
+        let json = r#"{
+            "tilewidth": 16, "tileheight": 16, "infinite": false,
+            "tilesets": [{"firstgid": 5, "name": "chars", "tiles": [
+                {"id": 0, "type": "Warden"},
+                {"id": 1, "image": "chars/prisoner.png"}
+            ]}],
+            "layers": [
+                {"type": "group", "name": "g", "layers": [
+                    {"type": "tilelayer", "name": "t", "width": 2, "data": [0, 5]}
+                ]},
+                {"type": "objectgroup", "name": "o", "objects": [
+                    {"name": "p", "gid": 6, "x": 16, "y": 48, "width": 16, "height": 16,
+                     "rotation": 90, "properties": [{"name": "circuit", "type": "int", "value": 1}]}
+                ]}
+            ]
+        }"#;
+        let map = read_json(json, Path::new("")).unwrap().to_map().unwrap();
+
+        // Turned clockwise around its bottom left corner, from (1, -2) down into (1, -3).
+        let mut prisoner = ItemInfo::new(Item::Prisoner, cell(1, -3), 270.0);
+        prisoner.properties.circuit = 1;
+        assert_eq!(
+            map.items,
+            vec![ItemInfo::new(Item::Warden, cell(1, 0), 0.0), prisoner]
+        );
+    }
+
+    #[test]
+    fn decodes_base64() {
+        // 1, then 2 flipped horizontally.
+        let gids = decode_gids(Some("base64"), None, " AQAAAAIAAIA= ").unwrap();
+        assert_eq!(gids, vec![1, 2 | FLIPPED_HORIZONTALLY]);
+        assert!(decode_gids(Some("base64"), Some("zlib"), "").is_err());
+    }
+
+    #[test]
+    fn rejects_mirrored_tiles() {
+        let tmx = TMX.replace("2684354561", "2147483649");
+        let tiled = read_tmx(&tmx, Path::new("")).unwrap();
+        assert!(tiled.to_map().is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_items() {
+        let tmx = TMX.replace(r#"class="Wall""#, r#"class="Sofa""#);
+        let tiled = read_tmx(&tmx, Path::new("")).unwrap();
+        assert!(tiled.to_map().is_err());
+    }
+}
@@ -0,0 +1,459 @@
+//! Scripted events saved with a level, like every wire in a block failing two minutes in, or a
+//! door shutting once a prisoner walks through it. Each trigger has a condition, checked every
+//! fixed step by `run_triggers`, and actions that happen when it becomes true.
+//!
+//! In JSON maps a trigger looks like
+//!
+//! ```json
+//! {
+//!   "name": "lockdown",
+//!   "when": { "PrisonerIn": { "min": [0, 0], "max": [10, 4] } },
+//!   "then": [
+//!     { "SetDoors": { "doors": { "Circuit": 2 }, "open": false } },
+//!     { "Notify": "Block B is locked down." }
+//!   ]
+//! }
+//! ```
+
+use crate::balance::Balance;
+use crate::game::{change_door_state, Door, Escaping, Headless, Prisoner};
+use crate::map::{ItemInfo, PathfindingMap};
+use crate::position::{GridPosition, Position};
+use crate::wires::{damage_wire, Broken, Circuit, Damaged, Wire};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// How long a notification stays on screen.
+const NOTIFICATION_SECONDS: f32 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub struct Trigger {
+    /// Only used in logs.
+    #[serde(default)]
+    pub name: String,
+    pub when: Condition,
+    pub then: Vec<Action>,
+    /// Fire each time the condition becomes true, instead of only the first time.
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+/// A rectangle of cells, including both corners.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub struct Region {
+    pub min: GridPosition,
+    pub max: GridPosition,
+}
+
+impl Region {
+    pub fn contains(&self, cell: &GridPosition) -> bool {
+        (self.min.0.x..=self.max.0.x).contains(&cell.0.x)
+            && (self.min.0.y..=self.max.0.y).contains(&cell.0.y)
+    }
+}
+
+/// Which doors or wires a condition or action is about. Doors and wires are matched by the cell
+/// they're placed on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub enum Selector {
+    All,
+    Circuit(u32),
+    Region(Region),
+}
+
+impl Selector {
+    pub fn matches(&self, circuit: u32, cell: &GridPosition) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Circuit(c) => *c == circuit,
+            Selector::Region(region) => region.contains(cell),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    /// Seconds since the level started.
+    After(f64),
+    /// Any prisoner is standing in the region.
+    PrisonerIn(Region),
+    /// Any prisoner is on their way to an exit.
+    PrisonerEscaping,
+    /// Any of the doors is open.
+    DoorsOpen(Selector),
+    /// Any of the wires is broken.
+    WiresBroken(Selector),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+// The borsh derive requires `Vec<Condition>: BorshSerialize` to implement it for `Condition`,
+// which never resolves, so these write the same layout by hand: the variant index, then its value.
+impl BorshSerialize for Condition {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Condition::After(seconds) => {
+                BorshSerialize::serialize(&0u8, writer)?;
+                BorshSerialize::serialize(seconds, writer)
+            }
+            Condition::PrisonerIn(region) => {
+                BorshSerialize::serialize(&1u8, writer)?;
+                BorshSerialize::serialize(region, writer)
+            }
+            Condition::PrisonerEscaping => BorshSerialize::serialize(&2u8, writer),
+            Condition::DoorsOpen(selector) => {
+                BorshSerialize::serialize(&3u8, writer)?;
+                BorshSerialize::serialize(selector, writer)
+            }
+            Condition::WiresBroken(selector) => {
+                BorshSerialize::serialize(&4u8, writer)?;
+                BorshSerialize::serialize(selector, writer)
+            }
+            Condition::All(conditions) => {
+                BorshSerialize::serialize(&5u8, writer)?;
+                BorshSerialize::serialize(conditions, writer)
+            }
+            Condition::Any(conditions) => {
+                BorshSerialize::serialize(&6u8, writer)?;
+                BorshSerialize::serialize(conditions, writer)
+            }
+        }
+    }
+}
+
+impl BorshDeserialize for Condition {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        Ok(match <u8 as BorshDeserialize>::deserialize(buf)? {
+            0 => Condition::After(<f64 as BorshDeserialize>::deserialize(buf)?),
+            1 => Condition::PrisonerIn(<Region as BorshDeserialize>::deserialize(buf)?),
+            2 => Condition::PrisonerEscaping,
+            3 => Condition::DoorsOpen(<Selector as BorshDeserialize>::deserialize(buf)?),
+            4 => Condition::WiresBroken(<Selector as BorshDeserialize>::deserialize(buf)?),
+            5 => Condition::All(<Vec<_> as BorshDeserialize>::deserialize(buf)?),
+            6 => Condition::Any(<Vec<_> as BorshDeserialize>::deserialize(buf)?),
+            v => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Unknown condition {}", v),
+                ))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub enum Action {
+    /// Starts the wires smoking, the same as when they're damaged at random. Wires that are
+    /// already damaged or broken are left alone.
+    DamageWires(Selector),
+    SetDoors {
+        doors: Selector,
+        open: bool,
+    },
+    /// Shows the text on screen for a few seconds.
+    Notify(String),
+}
+
+/// What conditions can see of the game, gathered once per step.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub elapsed: f64,
+    /// Each prisoner's cell, and whether they're escaping.
+    pub prisoners: Vec<(GridPosition, bool)>,
+    /// Each door's circuit, cell, and whether it's open.
+    pub doors: Vec<(u32, GridPosition, bool)>,
+    /// Each wire's circuit, cell, and whether it's broken.
+    pub wires: Vec<(u32, GridPosition, bool)>,
+}
+
+impl Condition {
+    pub fn holds(&self, snapshot: &Snapshot) -> bool {
+        let any = |parts: &[(u32, GridPosition, bool)], selector: &Selector| {
+            parts
+                .iter()
+                .any(|(circuit, cell, on)| *on && selector.matches(*circuit, cell))
+        };
+        match self {
+            Condition::After(seconds) => snapshot.elapsed >= *seconds,
+            Condition::PrisonerIn(region) => snapshot
+                .prisoners
+                .iter()
+                .any(|(cell, _)| region.contains(cell)),
+            Condition::PrisonerEscaping => snapshot.prisoners.iter().any(|(_, e)| *e),
+            Condition::DoorsOpen(selector) => any(&snapshot.doors, selector),
+            Condition::WiresBroken(selector) => any(&snapshot.wires, selector),
+            Condition::All(conditions) => conditions.iter().all(|c| c.holds(snapshot)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.holds(snapshot)),
+        }
+    }
+}
+
+/// The current level's triggers. Replaced by `game::setup`.
+#[derive(Debug, Default)]
+pub struct LevelTriggers {
+    triggers: Vec<Trigger>,
+    /// Whether each condition held on the last step, so triggers fire when it becomes true.
+    held: Vec<bool>,
+    fired: Vec<bool>,
+    elapsed: f64,
+}
+
+impl LevelTriggers {
+    pub fn new(triggers: Vec<Trigger>) -> Self {
+        Self {
+            held: vec![false; triggers.len()],
+            fired: vec![false; triggers.len()],
+            triggers,
+            elapsed: 0.0,
+        }
+    }
+
+    /// The triggers to fire for this snapshot.
+    pub fn step(&mut self, snapshot: &Snapshot) -> Vec<&Trigger> {
+        let mut firing = vec![];
+        for (idx, trigger) in self.triggers.iter().enumerate() {
+            let holds = trigger.when.holds(snapshot);
+            let became_true = holds && !self.held[idx];
+            self.held[idx] = holds;
+            if became_true && (trigger.repeat || !self.fired[idx]) {
+                self.fired[idx] = true;
+                firing.push(trigger);
+            }
+        }
+        firing
+    }
+}
+
+/// Text from `Action::Notify` and scripts, and how long each is left on screen.
+#[derive(Debug, Default)]
+pub struct Notifications(pub Vec<(String, Timer)>);
+
+impl Notifications {
+    /// Also logged, since there's nothing on screen when headless.
+    pub fn push(&mut self, text: &str) {
+        info!("{}", text);
+        let timer = Timer::from_seconds(NOTIFICATION_SECONDS, false);
+        self.0.push((text.to_string(), timer));
+    }
+}
+
+pub fn run_triggers(
+    mut commands: Commands,
+    balance: Res<Balance>,
+    mut triggers: ResMut<LevelTriggers>,
+    mut pathfinding_map: ResMut<PathfindingMap>,
+    mut notifications: ResMut<Notifications>,
+    prisoners: Query<(&Position, Option<&Escaping>), With<Prisoner>>,
+    doors: Query<(Entity, &Door, &Circuit, &GridPosition, &ItemInfo)>,
+    wires: Query<
+        (
+            Entity,
+            &Circuit,
+            &GridPosition,
+            Option<&Damaged>,
+            Option<&Broken>,
+        ),
+        With<Wire>,
+    >,
+) {
+    if triggers.triggers.is_empty() {
+        return;
+    }
+    // Counted in fixed steps rather than read from `Time`, so replays fire at the same step.
+    triggers.elapsed += balance.fixed_step;
+
+    let snapshot = Snapshot {
+        elapsed: triggers.elapsed,
+        prisoners: prisoners
+            .iter()
+            .map(|(pos, escaping)| (pos.nearest_cell(), escaping.is_some()))
+            .collect(),
+        doors: doors
+            .iter()
+            .map(|(_, door, circuit, cell, _)| (circuit.0, *cell, door.0))
+            .collect(),
+        wires: wires
+            .iter()
+            .map(|(_, circuit, cell, _, broken)| (circuit.0, *cell, broken.is_some()))
+            .collect(),
+    };
+
+    for trigger in triggers.step(&snapshot) {
+        info!("Trigger {:?} fired.", trigger.name);
+        for action in &trigger.then {
+            match action {
+                Action::DamageWires(selector) => {
+                    for (entity, circuit, cell, damaged, broken) in wires.iter() {
+                        let intact = damaged.is_none() && broken.is_none();
+                        if intact && selector.matches(circuit.0, cell) {
+                            damage_wire(&mut commands, entity, &balance);
+                        }
+                    }
+                }
+                Action::SetDoors {
+                    doors: selector,
+                    open,
+                } => {
+                    for (entity, door, circuit, cell, item_info) in doors.iter() {
+                        if door.0 != *open && selector.matches(circuit.0, cell) {
+                            change_door_state(
+                                &mut commands,
+                                &mut pathfinding_map,
+                                entity,
+                                cell,
+                                item_info,
+                                *open,
+                            );
+                        }
+                    }
+                }
+                Action::Notify(text) => notifications.push(text),
+            }
+        }
+    }
+}
+
+pub fn notifications_ui(
+    egui_context: ResMut<EguiContext>,
+    time: Res<Time>,
+    mut notifications: ResMut<Notifications>,
+    headless: Res<Headless>,
+) {
+    for (_, timer) in notifications.0.iter_mut() {
+        timer.tick(time.delta());
+    }
+    notifications.0.retain(|(_, timer)| !timer.finished());
+    if headless.0 || notifications.0.is_empty() {
+        return;
+    }
+    egui::Window::new("Notifications")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 20.0])
+        .show(egui_context.ctx(), |ui| {
+            for (text, _) in &notifications.0 {
+                ui.heading(text);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(min: (i32, i32), max: (i32, i32)) -> Region {
+        Region {
+            min: GridPosition::new(min.0, min.1),
+            max: GridPosition::new(max.0, max.1),
+        }
+    }
+
+    fn trigger(when: Condition, repeat: bool) -> Trigger {
+        Trigger {
+            name: "test".into(),
+            when,
+            then: vec![Action::Notify("hello".into())],
+            repeat,
+        }
+    }
+
+    fn at(elapsed: f64) -> Snapshot {
+        Snapshot {
+            elapsed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn selectors() {
+        let cell = GridPosition::new(3, -1);
+        assert!(Selector::All.matches(0, &cell));
+        assert!(Selector::Circuit(2).matches(2, &cell));
+        assert!(!Selector::Circuit(2).matches(1, &cell));
+        assert!(Selector::Region(region((0, -1), (3, 5))).matches(0, &cell));
+        assert!(!Selector::Region(region((4, -1), (6, 5))).matches(0, &cell));
+    }
+
+    #[test]
+    fn conditions() {
+        let snapshot = Snapshot {
+            elapsed: 10.0,
+            prisoners: vec![(GridPosition::new(5, 5), false)],
+            doors: vec![(1, GridPosition::new(0, 0), true)],
+            wires: vec![(2, GridPosition::new(1, 0), false)],
+        };
+        assert!(Condition::After(10.0).holds(&snapshot));
+        assert!(!Condition::After(10.5).holds(&snapshot));
+        assert!(Condition::PrisonerIn(region((4, 4), (5, 5))).holds(&snapshot));
+        assert!(!Condition::PrisonerIn(region((0, 0), (4, 4))).holds(&snapshot));
+        assert!(!Condition::PrisonerEscaping.holds(&snapshot));
+        assert!(Condition::DoorsOpen(Selector::Circuit(1)).holds(&snapshot));
+        assert!(!Condition::DoorsOpen(Selector::Circuit(2)).holds(&snapshot));
+        assert!(!Condition::WiresBroken(Selector::All).holds(&snapshot));
+        assert!(
+            !Condition::All(vec![Condition::After(1.0), Condition::PrisonerEscaping])
+                .holds(&snapshot)
+        );
+        assert!(
+            Condition::Any(vec![Condition::After(1.0), Condition::PrisonerEscaping])
+                .holds(&snapshot)
+        );
+    }
+
+    #[test]
+    fn fires_once_when_the_condition_becomes_true() {
+        let mut triggers = LevelTriggers::new(vec![trigger(Condition::After(2.0), false)]);
+        assert!(triggers.step(&at(1.0)).is_empty());
+        assert_eq!(triggers.step(&at(2.0)).len(), 1);
+        assert!(triggers.step(&at(3.0)).is_empty());
+    }
+
+    #[test]
+    fn repeats_each_time_the_condition_becomes_true() {
+        let inside = Condition::PrisonerIn(region((0, 0), (0, 0)));
+        let mut triggers = LevelTriggers::new(vec![trigger(inside, true)]);
+        let mut snapshot = at(0.0);
+        let mut fired = vec![];
+        for cell in &[(0, 0), (0, 0), (1, 0), (0, 0)] {
+            snapshot.prisoners = vec![(GridPosition::new(cell.0, cell.1), false)];
+            fired.push(triggers.step(&snapshot).len());
+        }
+        assert_eq!(fired, vec![1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn reads_json() {
+        let json = r#"{
+            "when": { "PrisonerIn": { "min": [0, 0], "max": [10, 4] } },
+            "then": [
+                { "SetDoors": { "doors": { "Circuit": 2 }, "open": false } },
+                { "Notify": "Block B is locked down." }
+            ]
+        }"#;
+        let trigger: Trigger = serde_json::from_str(json).unwrap();
+        assert_eq!(trigger.when, Condition::PrisonerIn(region((0, 0), (10, 4))));
+        assert_eq!(trigger.then.len(), 2);
+        assert!(!trigger.repeat);
+    }
+
+    #[test]
+    fn nested_conditions_round_trip_through_borsh() {
+        let trigger = trigger(
+            Condition::All(vec![
+                Condition::After(30.0),
+                Condition::Any(vec![
+                    Condition::PrisonerEscaping,
+                    Condition::DoorsOpen(Selector::Circuit(1)),
+                    Condition::WiresBroken(Selector::Region(region((0, 0), (3, 3)))),
+                ]),
+                Condition::PrisonerIn(region((-1, -1), (1, 1))),
+            ]),
+            true,
+        );
+        let bytes = trigger.try_to_vec().unwrap();
+        assert_eq!(Trigger::try_from_slice(&bytes).unwrap(), trigger);
+    }
+}
@@ -1,10 +1,11 @@
+use crate::balance::Balance;
 use crate::game;
-use crate::game::{Alpha, Door};
+use crate::game::{Alpha, Door, GameRng};
 use crate::map::{ItemInfo, PathfindingMap};
 use crate::position::GridPosition;
 use bevy::prelude::*;
 use rand::prelude::IteratorRandom;
-use rand::{thread_rng, RngCore, random};
+use rand::{random, Rng};
 
 #[derive(Debug)]
 pub struct Smoke;
@@ -14,31 +15,40 @@ pub struct Smoking(Timer);
 
 pub fn damage_wires(
     mut commands: Commands,
+    balance: Res<Balance>,
     good_wires: Query<Entity, (With<Wire>, Without<Damaged>, Without<Broken>)>,
+    mut rng: ResMut<GameRng>,
 ) {
-    let mut rng = thread_rng();
-    // 1000 seems good
-    // 100 is good for testing
-    if rng.next_u32() % 1000 != 0 {
+    let rng = &mut rng.rng;
+    if !rng.gen_ratio(1, balance.wire_damage_one_in.max(1)) {
         return;
     }
 
-    let entities = good_wires.iter().choose_multiple(&mut rng, 1);
-    let ent = entities.get(0);
+    let entities = good_wires.iter().choose_multiple(rng, 1);
+    let ent = entities.first();
     info!("damaging: {:?}", ent);
     match ent {
-        Some(e) => {
-            commands
-                .entity(*e)
-                .insert(Damaged(Timer::from_seconds(2.0, false)))
-                .insert(Smoking(Timer::from_seconds(0.5, true)));
-        }
+        Some(e) => damage_wire(&mut commands, *e, &balance),
         None => {
             info!("No wires left to smoke");
         }
     };
 }
 
+/// Starts a wire smoking. It breaks after `wire_damaged_seconds` unless it's fixed.
+pub fn damage_wire(commands: &mut Commands, wire: Entity, balance: &Balance) {
+    commands
+        .entity(wire)
+        .insert(Damaged(Timer::from_seconds(
+            balance.wire_damaged_seconds,
+            false,
+        )))
+        .insert(Smoking(Timer::from_seconds(
+            balance.smoke_interval_seconds,
+            true,
+        )));
+}
+
 pub fn damaged_smoke(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
@@ -51,12 +61,12 @@ pub fn damaged_smoke(
             continue;
         }
 
-        let mut color_material: ColorMaterial = asset_server.load("effects/smoke.png").into();
+        let color_material: ColorMaterial = asset_server.load("effects/smoke.png").into();
         let material = materials.add(color_material);
         commands
             .spawn_bundle(SpriteBundle {
                 material: material.clone(),
-                transform: transform.clone(),
+                transform: *transform,
                 ..Default::default()
             })
             .insert(Smoke)
@@ -64,23 +74,23 @@ pub fn damaged_smoke(
     }
 }
 
+/// Broken wires opening every door is switched off for now.
+const OPEN_DOORS_ON_BROKEN_WIRES: bool = false;
+
 pub fn open_doors_if_any_wires_are_broken(
     mut commands: Commands,
     mut pathfinding_map: ResMut<PathfindingMap>,
-    broken_wires: Query<(Entity), (With<Wire>, With<Broken>)>,
+    broken_wires: Query<Entity, (With<Wire>, With<Broken>)>,
     doors: Query<(Entity, &ItemInfo, &GridPosition), With<Door>>,
 ) {
-    if random::<i64>() % 1000 == 0 {
-        info!("skipping open_doors_if_any_wires_are_broken");
+    if !OPEN_DOORS_ON_BROKEN_WIRES {
+        if random::<i64>() % 1000 == 0 {
+            info!("skipping open_doors_if_any_wires_are_broken");
+        }
+        return;
     }
-    return;
 
-    let mut found = false;
-    for _ in broken_wires.iter() {
-        found = true;
-        break;
-    }
-    if !found {
+    if broken_wires.iter().next().is_none() {
         return;
     }
 
@@ -108,7 +118,7 @@ pub fn damaged_check_if_broken(
             continue;
         }
 
-        let mut color_material: ColorMaterial = asset_server.load("cells/wire-broken.png").into();
+        let color_material: ColorMaterial = asset_server.load("cells/wire-broken.png").into();
         let material = materials.add(color_material);
         commands
             .entity(ent)
@@ -121,8 +131,8 @@ pub fn damaged_check_if_broken(
 
 pub fn move_smoke(
     mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
+    _materials: ResMut<Assets<ColorMaterial>>,
+    _asset_server: Res<AssetServer>,
     mut smokes: Query<
         (
             Entity,
@@ -133,7 +143,7 @@ pub fn move_smoke(
         With<Smoke>,
     >,
 ) {
-    for (ent, mut transform, mut material, mut alpha) in smokes.iter_mut() {
+    for (ent, mut transform, _material, mut alpha) in smokes.iter_mut() {
         alpha.0 -= 0.01;
         if alpha.0 <= 0.0 {
             commands.entity(ent).despawn();
@@ -163,6 +173,10 @@ pub fn move_smoke(
 #[derive(Debug)]
 pub struct Wire;
 
+/// Which circuit a wire or door is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Circuit(pub u32);
+
 #[derive(Debug)]
 pub struct Damaged(Timer);
 